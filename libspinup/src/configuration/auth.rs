@@ -0,0 +1,265 @@
+//! The auth module defines the extra headers and credentials that can be sent
+//! with file downloads.
+//!
+//! Credentials are never written in the configuration itself, they are read from
+//! an environment variable or a file when the download starts, and are kept in a
+//! [`Secret`](struct.Secret.html) so that they can't end up in the logs.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+use super::fixup_path;
+
+/// The credentials to download a file with
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum DownloadAuth {
+    /// HTTP basic authentication, with the password from `password_env` or `password_file`
+    Basic {
+        username: String,
+        password_env: Option<String>,
+        password_file: Option<String>,
+    },
+
+    /// A bearer token, from `token_env` or `token_file`
+    Bearer {
+        token_env: Option<String>,
+        token_file: Option<String>,
+    },
+}
+
+/// Credentials that have been read in for a download
+#[derive(Debug, PartialEq)]
+pub enum Credentials {
+    Basic { username: String, password: Secret },
+    Bearer(Secret),
+}
+
+impl DownloadAuth {
+    /// Read in the secret for these credentials from the environment or its file
+    pub fn credentials(&self) -> Result<Credentials> {
+        match self {
+            DownloadAuth::Basic {
+                username,
+                password_env,
+                password_file,
+            } => Ok(Credentials::Basic {
+                username: username.clone(),
+                password: read_secret("password", password_env, password_file)?,
+            }),
+            DownloadAuth::Bearer {
+                token_env,
+                token_file,
+            } => Ok(Credentials::Bearer(read_secret(
+                "token", token_env, token_file,
+            )?)),
+        }
+    }
+}
+
+fn read_secret(
+    name: &str,
+    from_env: &Option<String>,
+    from_file: &Option<String>,
+) -> Result<Secret> {
+    match (from_env, from_file) {
+        (Some(var), None) => env::var(var)
+            .map(Secret)
+            .map_err(|_| Error::from(format!("The {} variable `{}` is not set", name, var))),
+        (None, Some(file)) => {
+            let path = fixup_path(PathBuf::from(file)).ok_or_else(|| {
+                Error::from(format!("Unable to resolve the {} file {}", name, file))
+            })?;
+            fs::read_to_string(&path)
+                .map(|contents| Secret(contents.trim_end_matches(&['\r', '\n'][..]).to_owned()))
+                .map_err(|e| {
+                    Error::from(format!("Unable to read the {} file {}: {}", name, file, e))
+                })
+        }
+        _ => Err(Error::from(format!(
+            "Exactly one of `{0}_env` or `{0}_file` must be given",
+            name
+        ))),
+    }
+}
+
+/// A credential that is never shown by `Debug` or `Display`
+#[derive(Clone, PartialEq)]
+pub struct Secret(String);
+
+impl Secret {
+    /// The actual value of the secret, to send it to a server
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[redacted]")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[redacted]")
+    }
+}
+
+/// Extra headers to send when downloading files.
+///
+/// None of the values are shown when the headers are printed.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct HttpHeaders(pub BTreeMap<String, HttpHeaderValue>);
+
+/// The value of an extra header, either written in the configuration or, for those
+/// that hold a credential, read from `value_env` or `value_file`
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum HttpHeaderValue {
+    Value(String),
+    Secret {
+        value_env: Option<String>,
+        value_file: Option<String>,
+    },
+}
+
+impl HttpHeaderValue {
+    /// Read in the value of the header, from the environment or its file when it isn't inline
+    pub fn value(&self) -> Result<Secret> {
+        match self {
+            HttpHeaderValue::Value(value) => Ok(Secret(value.clone())),
+            HttpHeaderValue::Secret {
+                value_env,
+                value_file,
+            } => read_secret("value", value_env, value_file),
+        }
+    }
+}
+
+impl From<&str> for HttpHeaderValue {
+    fn from(value: &str) -> Self {
+        HttpHeaderValue::Value(value.to_owned())
+    }
+}
+
+impl fmt::Debug for HttpHeaderValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpHeaderValue::Value(_) => write!(f, "[redacted]"),
+            HttpHeaderValue::Secret {
+                value_env,
+                value_file,
+            } => f
+                .debug_struct("Secret")
+                .field("value_env", value_env)
+                .field("value_file", value_file)
+                .finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inline_secrets_are_rejected() {
+        let inline = r#"{ "type": "bearer", "token": "abc123" }"#;
+        assert!(serde_json::from_str::<DownloadAuth>(inline).is_err());
+
+        let from_env = r#"{ "type": "bearer", "token_env": "SPINUP_AUTH_TEST_TOKEN" }"#;
+        assert!(serde_json::from_str::<DownloadAuth>(from_env).is_ok());
+    }
+
+    #[test]
+    fn test_credentials_from_env_and_file() {
+        env::set_var("SPINUP_AUTH_TEST_TOKEN", "abc123");
+        let bearer = DownloadAuth::Bearer {
+            token_env: Some(String::from("SPINUP_AUTH_TEST_TOKEN")),
+            token_file: None,
+        };
+        match bearer.credentials().unwrap() {
+            Credentials::Bearer(token) => assert_eq!(token.expose(), "abc123"),
+            other => panic!("Unexpected credentials {:?}", other),
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("password");
+        fs::write(&file, "hunter2\n").unwrap();
+        let basic = DownloadAuth::Basic {
+            username: String::from("ci"),
+            password_env: None,
+            password_file: Some(file.to_string_lossy().into_owned()),
+        };
+        let credentials = basic.credentials().unwrap();
+        assert!(!format!("{:?}", credentials).contains("hunter2"));
+        match credentials {
+            Credentials::Basic { username, password } => {
+                assert_eq!(username, "ci");
+                assert_eq!(password.expose(), "hunter2");
+            }
+            other => panic!("Unexpected credentials {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_missing_secret_sources() {
+        let neither = DownloadAuth::Bearer {
+            token_env: None,
+            token_file: None,
+        };
+        assert!(neither.credentials().is_err());
+
+        let unset = DownloadAuth::Bearer {
+            token_env: Some(String::from("SPINUP_SURELY_UNSET_TOKEN")),
+            token_file: None,
+        };
+        assert_eq!(
+            unset.credentials().unwrap_err().to_string(),
+            "The token variable `SPINUP_SURELY_UNSET_TOKEN` is not set"
+        );
+    }
+
+    #[test]
+    fn test_header_values_are_redacted() {
+        let mut headers = BTreeMap::new();
+        headers.insert(String::from("Accept"), HttpHeaderValue::from("text/plain"));
+        headers.insert(String::from("X-Mirror"), HttpHeaderValue::from("abc123"));
+        let printed = format!("{:?}", HttpHeaders(headers));
+        assert!(printed.contains("Accept"));
+        assert!(!printed.contains("text/plain"));
+        assert!(!printed.contains("abc123"));
+    }
+
+    #[test]
+    fn test_header_values_from_env_and_file() {
+        env::set_var("SPINUP_AUTH_TEST_HEADER", "abc123");
+        let headers: HttpHeaders = serde_json::from_str(
+            r#"{ "Accept": "text/plain", "X-Api-Key": { "value_env": "SPINUP_AUTH_TEST_HEADER" } }"#,
+        )
+        .unwrap();
+        assert_eq!(headers.0["Accept"].value().unwrap().expose(), "text/plain");
+        assert_eq!(headers.0["X-Api-Key"].value().unwrap().expose(), "abc123");
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("key");
+        fs::write(&file, "def456\n").unwrap();
+        let from_file = HttpHeaderValue::Secret {
+            value_env: None,
+            value_file: Some(file.to_string_lossy().into_owned()),
+        };
+        assert_eq!(from_file.value().unwrap().expose(), "def456");
+
+        let unknown = r#"{ "X-Api-Key": { "value": "abc123" } }"#;
+        assert!(serde_json::from_str::<HttpHeaders>(unknown).is_err());
+    }
+}
@@ -1,28 +1,74 @@
 //! The command module defines various commands that can be run on
 //! their own, or alongside other operations.
 
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 
 use crate::error::{Error, Result};
 use crate::operations::RunnableOperation;
 
-use super::SystemDetails;
+use super::files::seconds;
+use super::{fixup_path, Condition, SystemDetails};
 
-/// The `CustomCommand` defines a shell command that consists
+/// The `CustomCommand` defines a command that consists
 /// of a command name and arguments to pass to it.
 ///
-/// **Note:** This command will be passed to `sh` via the `-c` option.
+/// By default the command is run directly, with each of `args` passed to it as-is, so no
+/// shell syntax such as pipes or globs is understood. Setting [`shell`](#structfield.shell),
+/// or giving a [`script`](#structfield.script) instead, runs it through a shell with `-c`.
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct CustomCommand {
-    /// The command to run, it will be passed to `sh`
+    /// The command to run. In shell mode this is a full command line, such as `curl -fsL https://git.io/chezmoi | sh`
+    #[serde(default)]
     pub command: String,
 
+    /// A script to run through the shell instead of `command`, which may span several lines
+    pub script: Option<String>,
+
+    /// Whether to run `command` through a shell, either `true` for `sh` or the shell to use, such as `bash`
+    pub shell: Option<ShellSetting>,
+
     /// Whether this command needs root privileges to run
     #[serde(default)]
     pub needs_root: bool,
 
-    /// Any arguments to pass to the command
+    /// Any arguments to pass to the command. In shell mode they are quoted and added to the
+    /// end of `command`, and a `script` gets them as its positional parameters, `$1` onwards
     pub args: Option<Vec<String>>,
+
+    /// Whether the run should carry on if this command fails
+    #[serde(default)]
+    pub continue_on_error: bool,
+
+    /// The directory to run the command in, which may start with `~`
+    pub cwd: Option<String>,
+
+    /// Whether to start the command with only the variables in [`env`](#structfield.env),
+    /// rather than the environment spinup was started with
+    #[serde(default)]
+    pub clear_env: bool,
+
+    /// Checks that skip the command when its work has already been done
+    #[serde(flatten)]
+    pub guards: CommandGuards,
+
+    /// How many seconds the command may run for before it is stopped, by default it has no limit
+    pub timeout: Option<f64>,
+
+    /// How many times to run the command again after it fails or times out, defaults to 0
+    pub retries: Option<u32>,
+
+    /// How many seconds to wait before the first retry, doubling after each one. Defaults to 1 second
+    pub retry_delay: Option<f64>,
+
+    /// Only run this command when the condition holds on the current system
+    pub when: Option<Condition>,
+
+    /// Environment variables to set for the command, these are kept when it runs with `sudo`
+    pub env: Option<BTreeMap<String, String>>,
 }
 
 impl CustomCommand {
@@ -36,40 +82,227 @@ impl CustomCommand {
     pub fn new(command: String, args: Option<Vec<String>>, needs_root: bool) -> Self {
         CustomCommand {
             command,
+            script: None,
+            shell: None,
             args,
             needs_root,
+            continue_on_error: false,
+            when: None,
+            cwd: None,
+            env: None,
+            clear_env: false,
+            guards: CommandGuards::default(),
+            timeout: None,
+            retries: None,
+            retry_delay: None,
+        }
+    }
+
+    /// The shell this command runs through, or `None` when it is run directly
+    pub fn shell_program(&self) -> Option<&str> {
+        match &self.shell {
+            Some(ShellSetting::Program(program)) => Some(program),
+            Some(ShellSetting::Enabled(true)) => Some(DEFAULT_SHELL),
+            _ if self.script.is_some() => Some(DEFAULT_SHELL),
+            _ => None,
+        }
+    }
+
+    /// The command line that is passed to the shell with `-c`
+    fn shell_command(&self) -> String {
+        let mut line = self.command.clone();
+        for arg in self.args.iter().flatten() {
+            line.push(' ');
+            line.push_str(&shell_quote(arg));
         }
+        line
+    }
+}
+
+/// The shell used when [`shell`](struct.CustomCommand.html#structfield.shell) is `true`,
+/// and to run the probes of [`CommandGuards`](struct.CommandGuards.html)
+pub(crate) const DEFAULT_SHELL: &str = "sh";
+
+/// The name that a `script` sees as `$0`
+const SCRIPT_NAME: &str = "spinup";
+
+/// Whether a [`CustomCommand`](struct.CustomCommand.html) runs through a shell
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ShellSetting {
+    /// `true` to run through `sh`, `false` to run the command directly
+    Enabled(bool),
+
+    /// The shell to run through, such as `bash` or `/bin/zsh`
+    Program(String),
+}
+
+/// Checks made just before a [`CustomCommand`](struct.CustomCommand.html) runs, so that
+/// running the same configuration again skips the commands that have already done their work.
+///
+/// Paths may start with `~`, and relative paths are found from the command's `cwd`.
+/// Probes are command lines run with `sh -c`, in the command's `cwd` and `env`, and through `sudo`
+/// when the command needs root.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct CommandGuards {
+    /// Skip the command when this path exists
+    pub creates: Option<String>,
+
+    /// Skip the command when this path does not exist
+    pub removes: Option<String>,
+
+    /// Skip the command when this probe succeeds
+    pub unless: Option<String>,
+
+    /// Skip the command when this probe fails
+    pub only_if: Option<String>,
+}
+
+impl CommandGuards {
+    /// Describe the first guard that says the command should be skipped, or `None` if it should run.
+    ///
+    /// The paths are checked before any probe is run, with `probe` reporting whether a probe succeeded.
+    pub fn unmet(
+        &self,
+        cwd: Option<&Path>,
+        probe: &mut dyn FnMut(&str) -> Result<bool>,
+    ) -> Result<Option<String>> {
+        let exists = |path: &str| {
+            fixup_path(PathBuf::from(path))
+                .map(|path| match cwd {
+                    Some(cwd) => cwd.join(path),
+                    None => path,
+                })
+                .is_some_and(|path| path.exists())
+        };
+
+        if let Some(path) = &self.creates {
+            if exists(path) {
+                return Ok(Some(format!("{} already exists", path)));
+            }
+        }
+
+        if let Some(path) = &self.removes {
+            if !exists(path) {
+                return Ok(Some(format!("{} does not exist", path)));
+            }
+        }
+
+        if let Some(check) = &self.unless {
+            if probe(check)? {
+                return Ok(Some(format!("`{}` succeeded", check)));
+            }
+        }
+
+        if let Some(check) = &self.only_if {
+            if !probe(check)? {
+                return Ok(Some(format!("`{}` failed", check)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Quote `arg` so that a POSIX shell reads it back as a single word, unchanged
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-+=%@,./:".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
     }
 }
 
 impl RunnableOperation for CustomCommand {
-    fn command_name(&self, _system_details: SystemDetails) -> Result<String> {
-        if self.command.is_empty() {
-            Err(Error::from("Cannot process a zero-length shell command"))
-        } else {
-            Ok(self.command.clone())
+    fn command_name(&self, _system_details: &SystemDetails) -> Result<String> {
+        match (&self.script, self.shell_program()) {
+            (Some(_), _) if !self.command.is_empty() => Err(Error::from(format!(
+                "`{}` has both a command and a script, only one can be given",
+                self.command
+            ))),
+            (Some(script), _) if script.trim().is_empty() => {
+                Err(Error::from("Cannot process an empty script"))
+            }
+            (None, _) if self.command.is_empty() => {
+                Err(Error::from("Cannot process a zero-length shell command"))
+            }
+            (_, Some(shell)) => Ok(shell.to_owned()),
+            (_, None) => Ok(self.command.clone()),
         }
     }
 
-    fn args(&self, _system_details: SystemDetails) -> Option<Vec<String>> {
-        self.args.clone()
+    fn args(&self, _system_details: &SystemDetails) -> Option<Vec<String>> {
+        match (&self.script, self.shell_program()) {
+            (Some(script), _) => {
+                let mut args = vec![
+                    String::from("-c"),
+                    script.clone(),
+                    String::from(SCRIPT_NAME),
+                ];
+                args.extend(self.args.iter().flatten().cloned());
+                Some(args)
+            }
+            (None, Some(_)) => Some(vec![String::from("-c"), self.shell_command()]),
+            (None, None) => self.args.clone(),
+        }
     }
 
-    fn needs_root(&self) -> bool {
+    fn needs_root(&self, _system_details: &SystemDetails) -> bool {
         self.needs_root
     }
+
+    fn continue_on_error(&self) -> bool {
+        self.continue_on_error
+    }
+
+    fn skip_reason(&self, system_details: &SystemDetails) -> Option<String> {
+        self.when
+            .as_ref()
+            .and_then(|when| when.unmet(system_details))
+    }
+
+    fn working_dir(&self) -> Option<PathBuf> {
+        self.cwd
+            .as_ref()
+            .and_then(|cwd| fixup_path(PathBuf::from(cwd)))
+    }
+
+    fn env(&self) -> Option<&BTreeMap<String, String>> {
+        self.env.as_ref()
+    }
+
+    fn clear_env(&self) -> bool {
+        self.clear_env
+    }
+
+    fn guards(&self) -> Option<&CommandGuards> {
+        Some(&self.guards)
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(seconds)
+    }
+
+    fn retries(&self) -> u32 {
+        self.retries.unwrap_or(0)
+    }
+
+    fn retry_delay(&self) -> Duration {
+        seconds(self.retry_delay.unwrap_or(1.0))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::configuration::{SystemDetails, TargetOperatingSystem};
-    use crate::operations::RunnableOperation;
+    use crate::operations::{resolve_command, CommandRunner, ProcessRunner, RunnableOperation};
 
     #[test]
     fn test_get_command_name() {
         let command = CustomCommand::new(String::from("fc-cache"), None, false);
-        let actual_res = command.command_name(SystemDetails::new(TargetOperatingSystem::Arch));
+        let actual_res = command.command_name(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_res.is_ok());
         let actual = actual_res.unwrap();
         assert_eq!(actual, String::from("fc-cache"));
@@ -78,26 +311,26 @@ mod tests {
     #[test]
     fn test_empty_command_err() {
         let command = CustomCommand::new(String::new(), None, false);
-        let actual_res = command.command_name(SystemDetails::new(TargetOperatingSystem::Arch));
+        let actual_res = command.command_name(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_res.is_err());
     }
 
     #[test]
     fn test_no_root_without_sudo() {
         let command = CustomCommand::new(String::from("git"), None, false);
-        assert!(!command.needs_root());
+        assert!(!command.needs_root(&SystemDetails::default()));
     }
 
     #[test]
     fn test_root() {
         let command = CustomCommand::new(String::from("systemctl"), None, true);
-        assert!(command.needs_root());
+        assert!(command.needs_root(&SystemDetails::default()));
     }
 
     #[test]
     fn test_not_root() {
         let command = CustomCommand::new(String::from("systemctl"), None, false);
-        assert!(!command.needs_root());
+        assert!(!command.needs_root(&SystemDetails::default()));
     }
 
     #[test]
@@ -110,7 +343,7 @@ mod tests {
             ]),
             false,
         );
-        let actual_opt = command.args(SystemDetails::new(TargetOperatingSystem::Arch));
+        let actual_opt = command.args(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_opt.is_some());
         let actual = actual_opt.unwrap();
         assert_eq!(
@@ -125,7 +358,187 @@ mod tests {
     #[test]
     fn test_get_none_args() {
         let command = CustomCommand::new(String::from("git"), None, false);
-        let actual = command.args(SystemDetails::new(TargetOperatingSystem::Debian));
+        let actual = command.args(&SystemDetails::new(TargetOperatingSystem::Debian));
         assert!(actual.is_none());
     }
+
+    fn from_yaml(yaml: &str) -> CustomCommand {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_shell_mode_quotes_args() {
+        let system = SystemDetails::new(TargetOperatingSystem::Arch);
+        let command = from_yaml(
+            "command: curl -fsL https://git.io/chezmoi | sh\nshell: true\nargs: [\"it's here\", \"-s\"]\n",
+        );
+        assert_eq!(command.command_name(&system).unwrap(), "sh");
+        assert_eq!(
+            command.args(&system).unwrap(),
+            vec![
+                String::from("-c"),
+                String::from("curl -fsL https://git.io/chezmoi | sh 'it'\\''s here' -s")
+            ]
+        );
+
+        let bash = from_yaml("command: echo $HOME\nshell: /bin/bash\n");
+        assert_eq!(bash.command_name(&system).unwrap(), "/bin/bash");
+        let direct = from_yaml("command: ls\nshell: false\nargs: [\"|\"]\n");
+        assert_eq!(direct.command_name(&system).unwrap(), "ls");
+        assert_eq!(direct.args(&system).unwrap(), vec![String::from("|")]);
+    }
+
+    #[test]
+    fn test_script_gets_args_as_parameters() {
+        let system = SystemDetails::new(TargetOperatingSystem::Arch);
+        let script = from_yaml("script: |\n  cd \"$1\"\n  make install\nargs: [~/src/tool]\n");
+        assert_eq!(script.command_name(&system).unwrap(), "sh");
+        assert_eq!(
+            script.args(&system).unwrap(),
+            vec![
+                String::from("-c"),
+                String::from("cd \"$1\"\nmake install\n"),
+                String::from("spinup"),
+                String::from("~/src/tool"),
+            ]
+        );
+
+        let both = from_yaml("command: ls\nscript: ls\n");
+        assert!(both.command_name(&system).is_err());
+    }
+
+    #[test]
+    fn test_shell_commands_run_the_same_with_sudo() {
+        let system = SystemDetails::new(TargetOperatingSystem::Arch);
+        let mut command = from_yaml("command: echo a | tee /etc/motd\nshell: true\n");
+        let direct = resolve_command(&command, &system).unwrap();
+        command.needs_root = true;
+        let sudo = resolve_command(&command, &system).unwrap();
+        assert_eq!(sudo.command, "sudo");
+        assert_eq!(sudo.args[0], direct.command);
+        assert_eq!(sudo.args[1..], direct.args[..]);
+    }
+
+    #[test]
+    fn test_script_runs_in_a_real_shell() {
+        let script =
+            from_yaml("script: |\n  printf '%s' \"$1\" | tr a-z A-Z\nargs: [\"it's | fine\"]\n");
+        let system = SystemDetails::new(TargetOperatingSystem::Arch);
+        let resolved = resolve_command(&script, &system).unwrap();
+        let output = ProcessRunner::default().spawn(&resolved).unwrap();
+        assert_eq!(output.stdout, b"IT'S | FINE");
+
+        let line = from_yaml("command: printf '%s'\nshell: true\nargs: [\"$HOME | x\"]\n");
+        let resolved = resolve_command(&line, &system).unwrap();
+        let output = ProcessRunner::default().spawn(&resolved).unwrap();
+        assert_eq!(output.stdout, b"$HOME | x");
+    }
+
+    #[test]
+    fn test_cwd_and_env_are_kept_with_sudo() {
+        let system = SystemDetails::new(TargetOperatingSystem::Arch);
+        let mut command = from_yaml(
+            "command: make\nargs: [install]\ncwd: ~/src/tool\nenv: { PREFIX: /usr/local, CC: clang }\n",
+        );
+        let direct = resolve_command(&command, &system).unwrap();
+        assert_eq!(direct.command, "make");
+        assert_eq!(direct.env.get("PREFIX").unwrap(), "/usr/local");
+        assert_eq!(direct.cwd, dirs::home_dir().map(|h| h.join("src/tool")));
+
+        command.needs_root = true;
+        command.clear_env = true;
+        let sudo = resolve_command(&command, &system).unwrap();
+        assert_eq!(
+            sudo.to_string(),
+            format!(
+                "sudo env -i CC=clang PREFIX=/usr/local make install (in {})",
+                direct.cwd.unwrap().display()
+            )
+        );
+        assert!(sudo.env.is_empty());
+    }
+
+    #[test]
+    fn test_cwd_and_env_apply_to_the_process() {
+        let system = SystemDetails::new(TargetOperatingSystem::Arch);
+        let dir = tempfile::tempdir().unwrap();
+        let mut command = from_yaml(
+            "script: printf '%s %s' \"$(pwd)\" \"$SPINUP_CMD_TEST\"\nenv: { SPINUP_CMD_TEST: set }\n",
+        );
+        command.cwd = Some(dir.path().to_string_lossy().into_owned());
+        let output = ProcessRunner::default()
+            .spawn(&resolve_command(&command, &system).unwrap())
+            .unwrap();
+        let pwd = dir.path().canonicalize().unwrap();
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!("{} set", pwd.display())
+        );
+
+        command.cwd = Some(dir.path().join("missing").to_string_lossy().into_owned());
+        assert!(ProcessRunner::default()
+            .spawn(&resolve_command(&command, &system).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_path_guards() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("done"), b"").unwrap();
+        let mut never_probe = |_: &str| -> Result<bool> { panic!("No probe should run") };
+
+        let created = from_yaml("command: make\ncreates: done\n");
+        assert_eq!(
+            created
+                .guards
+                .unmet(Some(dir.path()), &mut never_probe)
+                .unwrap(),
+            Some(String::from("done already exists"))
+        );
+        assert_eq!(created.guards.unmet(None, &mut never_probe).unwrap(), None);
+
+        let removes = from_yaml("command: rm\nremoves: gone\n");
+        assert_eq!(
+            removes
+                .guards
+                .unmet(Some(dir.path()), &mut never_probe)
+                .unwrap(),
+            Some(String::from("gone does not exist"))
+        );
+    }
+
+    #[test]
+    fn test_probe_guards() {
+        let command = from_yaml(
+            "command: cargo install ripgrep\nunless: command -v rg\nonly_if: test -d ~/.cargo\n",
+        );
+        let mut probed = Vec::new();
+        let reason = command
+            .guards
+            .unmet(None, &mut |check| {
+                probed.push(check.to_owned());
+                Ok(check.starts_with("test"))
+            })
+            .unwrap();
+        assert_eq!(reason, None);
+        assert_eq!(probed, vec!["command -v rg", "test -d ~/.cargo"]);
+
+        let reason = command.guards.unmet(None, &mut |_| Ok(true)).unwrap();
+        assert_eq!(reason, Some(String::from("`command -v rg` succeeded")));
+        let reason = command.guards.unmet(None, &mut |_| Ok(false)).unwrap();
+        assert_eq!(reason, Some(String::from("`test -d ~/.cargo` failed")));
+    }
+
+    #[test]
+    fn test_full_command_serializes_to_toml() {
+        let command = from_yaml(
+            "command: make install\nneeds_root: true\nargs: [\"PREFIX=/usr\"]\ncwd: ~/src\nenv: { CC: clang }\nclear_env: true\ncreates: /usr/bin/tool\ntimeout: 60\nretries: 2\nwhen: { os: arch }\n",
+        );
+        let toml = toml::to_string_pretty(&command).unwrap();
+        let actual: CustomCommand = toml::from_str(&toml).unwrap();
+        assert_eq!(toml::to_string_pretty(&actual).unwrap(), toml);
+        assert_eq!(actual.env, command.env);
+        assert_eq!(actual.when, command.when);
+        assert_eq!(actual.guards, command.guards);
+    }
 }
@@ -1,12 +1,14 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
 use crate::error::{Error, Result};
 use crate::operations::RunnableOperation;
 
-use super::command::CustomCommand;
-use super::{SystemDetails, Validatable};
+use super::command::{CommandGuards, CustomCommand};
+use super::{Condition, SystemDetails, Validatable};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct OrderedCommand {
@@ -25,6 +27,9 @@ pub struct CommandSet {
 
     /// The set of commands to run in the specified order
     pub(crate) commands: Vec<OrderedCommand>,
+
+    /// Only run this set when the condition holds on the current system
+    pub(crate) when: Option<Condition>,
 }
 
 impl Validatable for CommandSet {
@@ -64,16 +69,52 @@ impl CommandSet {
 }
 
 impl RunnableOperation for OrderedCommand {
-    fn command_name(&self, system_details: SystemDetails) -> Result<String> {
+    fn command_name(&self, system_details: &SystemDetails) -> Result<String> {
         self.command.command_name(system_details)
     }
 
-    fn args(&self, system_details: SystemDetails) -> Option<Vec<String>> {
+    fn args(&self, system_details: &SystemDetails) -> Option<Vec<String>> {
         self.command.args(system_details)
     }
 
-    fn needs_root(&self) -> bool {
-        self.command.needs_root()
+    fn needs_root(&self, system_details: &SystemDetails) -> bool {
+        self.command.needs_root(system_details)
+    }
+
+    fn continue_on_error(&self) -> bool {
+        self.command.continue_on_error()
+    }
+
+    fn skip_reason(&self, system_details: &SystemDetails) -> Option<String> {
+        self.command.skip_reason(system_details)
+    }
+
+    fn working_dir(&self) -> Option<PathBuf> {
+        self.command.working_dir()
+    }
+
+    fn env(&self) -> Option<&BTreeMap<String, String>> {
+        self.command.env()
+    }
+
+    fn clear_env(&self) -> bool {
+        self.command.clear_env()
+    }
+
+    fn guards(&self) -> Option<&CommandGuards> {
+        self.command.guards()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.command.timeout()
+    }
+
+    fn retries(&self) -> u32 {
+        self.command.retries()
+    }
+
+    fn retry_delay(&self) -> Duration {
+        self.command.retry_delay()
     }
 }
 
@@ -121,6 +162,7 @@ mod tests {
                     command: CustomCommand::new("mv".to_string(), None, false),
                 },
             ],
+            when: None,
         };
         let actual = command_set.validate();
         assert!(actual.is_err());
@@ -140,6 +182,7 @@ mod tests {
                     command: CustomCommand::new("cd".to_string(), None, false),
                 },
             ],
+            when: None,
         };
         let actual = command_set.validate();
         assert!(actual.is_ok());
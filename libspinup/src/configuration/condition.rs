@@ -0,0 +1,212 @@
+//! The `condition` module defines the `when` conditions that limit an
+//! operation to the hosts it makes sense on.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::{fixup_path, SystemDetails, TargetOperatingSystem};
+
+/// A set of facts about the host that must all hold for an operation to run.
+///
+/// Every field is optional, and a condition with no fields set always holds.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct Condition {
+    /// The distro, matched the same way as [`target_os`](struct.DistroPackages.html#structfield.target_os)
+    pub os: Option<String>,
+
+    /// The `VERSION_ID` from `/etc/os-release`, such as `20.04`
+    pub os_version: Option<String>,
+
+    /// The CPU architecture, such as `x86_64` or `aarch64`
+    pub arch: Option<String>,
+
+    /// The hostname of the machine
+    pub hostname: Option<String>,
+
+    /// A path that must exist, a leading `~` is replaced with the home directory
+    pub file_exists: Option<String>,
+
+    /// A command that must be found on the `PATH`
+    pub command_exists: Option<String>,
+
+    /// Environment variables that must be set to the given values
+    pub env: Option<BTreeMap<String, String>>,
+}
+
+impl Condition {
+    /// Describe the first part of this condition that does not hold on the system
+    /// described by `system_details`, or `None` if every part holds
+    pub fn unmet(&self, system_details: &SystemDetails) -> Option<String> {
+        if let Some(os) = &self.os {
+            if TargetOperatingSystem::from(&os[..]) != system_details.current_os() {
+                return Some(format!("the os is not {}", os));
+            }
+        }
+
+        if let Some(version) = &self.os_version {
+            if system_details.os_version() != Some(&version[..]) {
+                return Some(format!("the os version is not {}", version));
+            }
+        }
+
+        if let Some(arch) = &self.arch {
+            if system_details.arch() != arch {
+                return Some(format!("the architecture is not {}", arch));
+            }
+        }
+
+        if let Some(hostname) = &self.hostname {
+            if system_details.hostname() != Some(&hostname[..]) {
+                return Some(format!("the hostname is not {}", hostname));
+            }
+        }
+
+        if let Some(vars) = &self.env {
+            for (name, value) in vars {
+                if env::var(name).ok().as_ref() != Some(value) {
+                    return Some(format!("{} is not set to {}", name, value));
+                }
+            }
+        }
+
+        if let Some(file) = &self.file_exists {
+            if !fixup_path(PathBuf::from(file)).is_some_and(|p| p.exists()) {
+                return Some(format!("{} does not exist", file));
+            }
+        }
+
+        if let Some(command) = &self.command_exists {
+            if !command_on_path(command) {
+                return Some(format!("{} is not on the PATH", command));
+            }
+        }
+
+        None
+    }
+}
+
+/// Whether `command` names an executable, either directly or within a directory on the `PATH`
+fn command_on_path(command: &str) -> bool {
+    if command.contains('/') {
+        return is_executable(Path::new(command));
+    }
+
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| is_executable(&dir.join(command))))
+        .unwrap_or(false)
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system() -> SystemDetails {
+        SystemDetails::new(TargetOperatingSystem::Debian)
+            .with_os_version(String::from("20.04"))
+            .with_hostname(String::from("laptop"))
+    }
+
+    #[test]
+    fn test_empty_condition_holds() {
+        assert_eq!(Condition::default().unmet(&system()), None);
+    }
+
+    #[test]
+    fn test_os_facts() {
+        let condition = Condition {
+            os: Some(String::from("ubuntu")),
+            os_version: Some(String::from("20.04")),
+            arch: Some(String::from(env::consts::ARCH)),
+            hostname: Some(String::from("laptop")),
+            ..Condition::default()
+        };
+        assert_eq!(condition.unmet(&system()), None);
+
+        let other_os = Condition {
+            os: Some(String::from("arch")),
+            ..Condition::default()
+        };
+        assert_eq!(
+            other_os.unmet(&system()),
+            Some(String::from("the os is not arch"))
+        );
+
+        let other_host = Condition {
+            hostname: Some(String::from("desktop")),
+            ..Condition::default()
+        };
+        assert_eq!(
+            other_host.unmet(&system()),
+            Some(String::from("the hostname is not desktop"))
+        );
+    }
+
+    #[test]
+    fn test_env_condition() {
+        env::set_var("SPINUP_CONDITION_TEST", "yes");
+        let mut vars = BTreeMap::new();
+        vars.insert(String::from("SPINUP_CONDITION_TEST"), String::from("yes"));
+        let condition = Condition {
+            env: Some(vars.clone()),
+            ..Condition::default()
+        };
+        assert_eq!(condition.unmet(&system()), None);
+
+        vars.insert(String::from("SPINUP_CONDITION_TEST"), String::from("no"));
+        let condition = Condition {
+            env: Some(vars),
+            ..Condition::default()
+        };
+        assert!(condition.unmet(&system()).is_some());
+    }
+
+    #[test]
+    fn test_file_and_command_conditions() {
+        let exists = Condition {
+            file_exists: Some(String::from("/")),
+            command_exists: Some(String::from("sh")),
+            ..Condition::default()
+        };
+        assert_eq!(exists.unmet(&system()), None);
+
+        let missing_file = Condition {
+            file_exists: Some(String::from("/surely/not/a/real/path")),
+            ..Condition::default()
+        };
+        assert!(missing_file.unmet(&system()).is_some());
+
+        let missing_command = Condition {
+            command_exists: Some(String::from("surely-not-a-real-command")),
+            ..Condition::default()
+        };
+        assert_eq!(
+            missing_command.unmet(&system()),
+            Some(String::from("surely-not-a-real-command is not on the PATH"))
+        );
+    }
+
+    #[test]
+    fn test_full_condition_serializes_to_toml() {
+        let mut vars = BTreeMap::new();
+        vars.insert(String::from("DISPLAY"), String::from(":0"));
+        let condition = Condition {
+            os: Some(String::from("arch")),
+            env: Some(vars),
+            file_exists: Some(String::from("~/.config")),
+            command_exists: Some(String::from("git")),
+            ..Condition::default()
+        };
+        let toml = toml::to_string_pretty(&condition).unwrap();
+        assert_eq!(toml::from_str::<Condition>(&toml).unwrap(), condition);
+    }
+}
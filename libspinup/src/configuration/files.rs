@@ -6,19 +6,250 @@
 //! the target folder to download the files to, the files that
 //! should be downloaded, and a command that should be run after
 //! the download(s) finish.
-use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use super::CustomCommand;
+use reqwest::Url;
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::error::{self, Error};
+
+use super::{fixup_path, Condition, CustomCommand, DownloadAuth, HttpHeaders, Validatable};
 
 /// A `FileDownloadDefinition` defines a file to download
 /// and what to name it.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct FileDownloadDefinition {
-    /// The source of the file, this should be a publicly accessible URL.
+    /// The URL to download the file from, either this or [`github_release`](#structfield.github_release) must be given.
+    ///
+    /// A `file://` URL or a plain path is copied from the local system instead, with a relative
+    /// path being found from the directory of the configuration file.
+    #[serde(default)]
     pub source: String,
 
     /// The target filename to download to, this name will be appended to [`base_dir`](struct.FileDownloadOperation.html#structfield.base_dir)
     pub target: String,
+
+    /// The expected SHA-256 digest of the file, as hex. The file is not written if it does not match.
+    pub sha256: Option<String>,
+
+    /// The expected SHA-512 digest of the file, as hex. The file is not written if it does not match.
+    pub sha512: Option<String>,
+
+    /// The permissions to give the downloaded file, such as `"0755"`
+    pub mode: Option<FileMode>,
+
+    /// The user that should own the downloaded file, changing it requires root
+    pub owner: Option<String>,
+
+    /// The group that should own the downloaded file
+    pub group: Option<String>,
+
+    /// A GitHub release asset to download, its URL is looked up when the download starts
+    pub github_release: Option<GithubRelease>,
+
+    /// Unpack the downloaded file as an archive, before the `after_complete` command runs
+    pub extract: Option<ExtractDefinition>,
+
+    /// Extra headers to send when downloading this file, added to those of the operation
+    pub headers: Option<HttpHeaders>,
+
+    /// The credentials to download this file with, instead of those of the operation
+    pub auth: Option<DownloadAuth>,
+
+    /// The directory of the configuration file this was read from
+    #[serde(skip)]
+    pub config_dir: Option<PathBuf>,
+}
+
+impl FileDownloadDefinition {
+    /// The path of the file to copy, when [`source`](#structfield.source) is a `file://` URL or a
+    /// plain path rather than something to download
+    pub fn local_source(&self) -> error::Result<Option<PathBuf>> {
+        let path = match Url::parse(&self.source) {
+            Ok(url) if url.scheme() == "file" => url
+                .to_file_path()
+                .map_err(|_| Error::from(format!("{} is not a local file URL", self.source)))?,
+            Ok(_) => return Ok(None),
+            Err(_) if self.source.is_empty() => return Ok(None),
+            Err(_) => fixup_path(PathBuf::from(&self.source)).ok_or_else(|| {
+                Error::from(format!("Unable to resolve the path {}", self.source))
+            })?,
+        };
+
+        Ok(Some(match &self.config_dir {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path,
+        }))
+    }
+}
+
+impl Validatable for FileDownloadDefinition {
+    fn validate(&self) -> error::Result<()> {
+        match (self.source.is_empty(), &self.github_release) {
+            (false, None) | (true, Some(_)) => Ok(()),
+            _ => Err(Error::from(format!(
+                "The download of {} needs either a `source` or a `github_release`, but not both",
+                self.target
+            ))),
+        }
+    }
+}
+
+/// A file attached to a release of a GitHub repository
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct GithubRelease {
+    /// The repository the release belongs to, as `owner/repo`
+    pub repo: String,
+
+    /// The tag of the release, or `latest` for the newest release. Defaults to `latest`
+    #[serde(default = "GithubRelease::latest")]
+    pub tag: String,
+
+    /// A glob matching the name of the asset to download, such as `ripgrep-*-{arch}-unknown-linux-musl.tar.gz`.
+    /// `{arch}` and `{os}` are replaced with the architecture and OS of the current system
+    pub asset: String,
+
+    /// The base URL of the API, for GitHub Enterprise servers. Defaults to `https://api.github.com`
+    pub api_url: Option<String>,
+}
+
+impl GithubRelease {
+    fn latest() -> String {
+        String::from("latest")
+    }
+}
+
+impl fmt::Display for GithubRelease {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "github:{}@{} {}", self.repo, self.tag, self.asset)
+    }
+}
+
+/// Unix permission bits, written in octal the same way as for `chmod`.
+///
+/// Both strings and numbers are accepted, so `"0755"`, `"755"` and `755` are all the same mode.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FileMode(pub u32);
+
+impl FileMode {
+    fn parse(text: &str) -> Option<FileMode> {
+        let digits = text.trim().trim_start_matches("0o");
+        u32::from_str_radix(digits, 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+            .map(FileMode)
+    }
+}
+
+impl fmt::Display for FileMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04o}", self.0)
+    }
+}
+
+impl Serialize for FileMode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for FileMode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Text(String),
+            Number(u64),
+        }
+
+        let text = match Raw::deserialize(deserializer)? {
+            Raw::Text(text) => text,
+            Raw::Number(number) => number.to_string(),
+        };
+        FileMode::parse(&text).ok_or_else(|| {
+            de::Error::custom(format!("`{}` is not an octal file mode such as 0755", text))
+        })
+    }
+}
+
+/// The kinds of archive that a download can be extracted from
+#[derive(Debug, Deserialize, Serialize, Copy, Clone, PartialEq)]
+pub enum ArchiveFormat {
+    /// A gzip compressed tarball, `.tar.gz` or `.tgz`
+    #[serde(rename = "tar.gz")]
+    TarGz,
+
+    /// An xz compressed tarball, `.tar.xz` or `.txz`
+    #[serde(rename = "tar.xz")]
+    TarXz,
+
+    /// A zip archive, `.zip`
+    #[serde(rename = "zip")]
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Guess the format of an archive from its file name
+    pub fn from_file_name(name: &str) -> Option<ArchiveFormat> {
+        let name = name.to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if name.ends_with(".tar.xz") || name.ends_with(".txz") {
+            Some(ArchiveFormat::TarXz)
+        } else if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// What to do when a file being extracted already exists
+#[derive(Debug, Deserialize, Serialize, Copy, Clone, Default, PartialEq)]
+#[serde(rename_all(deserialize = "lowercase", serialize = "lowercase"))]
+pub enum OverwritePolicy {
+    /// Replace the existing file (default)
+    #[default]
+    Replace,
+
+    /// Leave the existing file as it is
+    Skip,
+
+    /// Fail the extraction
+    Error,
+}
+
+/// How to extract a downloaded archive
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct ExtractDefinition {
+    /// The directory to extract into, relative to [`base_dir`](struct.FileDownloadOperation.html#structfield.base_dir).
+    /// Defaults to `base_dir` itself
+    pub dest: Option<String>,
+
+    /// The format of the archive, guessed from the [`target`](struct.FileDownloadDefinition.html#structfield.target)
+    /// name when not given
+    pub format: Option<ArchiveFormat>,
+
+    /// How many leading path components to remove from each entry, like `tar --strip-components`
+    #[serde(default)]
+    pub strip_components: usize,
+
+    /// What to do with entries that already exist, defaults to [`OverwritePolicy::Replace`](enum.OverwritePolicy.html#variant.Replace)
+    #[serde(default)]
+    pub overwrite: OverwritePolicy,
+}
+
+impl ExtractDefinition {
+    /// The directory the archive will be extracted into, for a download into `base_path`
+    pub fn destination(&self, base_path: &Path) -> PathBuf {
+        match &self.dest {
+            Some(dest) => base_path.join(dest),
+            None => base_path.to_path_buf(),
+        }
+    }
 }
 
 /// The container definition for a full set of file downloads.
@@ -27,9 +258,339 @@ pub struct FileDownloadOperation {
     /// The directory to download all files into
     pub base_dir: Option<String>,
 
+    /// Whether the run should carry on if any of these downloads fail
+    #[serde(default)]
+    pub continue_on_error: bool,
+
+    /// Retry and timeout settings for these downloads, any that are not given here
+    /// come from the configuration's [`download_settings`](struct.Configuration.html#structfield.download_settings).
+    ///
+    /// All downloads share a connection pool, so one with its own `connect_timeout` gets a separate pool.
+    #[serde(flatten)]
+    pub settings: DownloadSettings,
+
     /// A command to run after all downloads complete
     pub after_complete: Option<CustomCommand>,
 
     /// The files to download
     pub files: Vec<FileDownloadDefinition>,
+
+    /// Only download these files when the condition holds on the current system
+    pub when: Option<Condition>,
+
+    /// Extra headers to send when downloading each of these files
+    pub headers: Option<HttpHeaders>,
+
+    /// The credentials to download these files with
+    pub auth: Option<DownloadAuth>,
+}
+
+/// Settings that control how persistently files are downloaded.
+///
+/// All durations are in seconds.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct DownloadSettings {
+    /// How many times to retry a download after a transient failure, defaults to 2
+    pub retries: Option<u32>,
+
+    /// How long to wait before the first retry, doubling after each one. Defaults to 1 second
+    pub retry_delay: Option<f64>,
+
+    /// How long to wait to connect to the server, defaults to 30 seconds
+    pub connect_timeout: Option<f64>,
+
+    /// How long to wait for the server to send the next part of a download, defaults to 60 seconds
+    pub read_timeout: Option<f64>,
+}
+
+impl DownloadSettings {
+    /// Fill in any settings that are not set here from `defaults`
+    pub fn or(&self, defaults: Option<&DownloadSettings>) -> DownloadSettings {
+        let defaults = defaults.cloned().unwrap_or_default();
+        DownloadSettings {
+            retries: self.retries.or(defaults.retries),
+            retry_delay: self.retry_delay.or(defaults.retry_delay),
+            connect_timeout: self.connect_timeout.or(defaults.connect_timeout),
+            read_timeout: self.read_timeout.or(defaults.read_timeout),
+        }
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(2)
+    }
+
+    pub fn retry_delay(&self) -> Duration {
+        seconds(self.retry_delay.unwrap_or(1.0))
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        seconds(self.connect_timeout.unwrap_or(30.0))
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        seconds(self.read_timeout.unwrap_or(60.0))
+    }
+}
+
+/// The configuration's `download_settings`, the [`DownloadSettings`](struct.DownloadSettings.html)
+/// for every download that does not set its own, along with the settings shared by all of the
+/// downloads in a run.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct GlobalDownloadSettings {
+    /// The settings for downloads that do not set their own
+    #[serde(flatten)]
+    pub defaults: DownloadSettings,
+
+    /// How many files can be downloaded at once, defaults to 8
+    pub max_concurrent: Option<usize>,
+
+    /// How many files can be downloaded from the same host at once, defaults to 4
+    pub max_per_host: Option<usize>,
+
+    /// The URL of a proxy to download through. When not given, the `HTTPS_PROXY` and `HTTP_PROXY`
+    /// environment variables are used
+    pub proxy: Option<String>,
+
+    /// Hosts, or domains starting with `.`, that are reached without the configured `proxy`
+    pub no_proxy: Option<Vec<String>>,
+}
+
+impl GlobalDownloadSettings {
+    /// Fill in any settings that are not set here from `defaults`
+    pub fn or(&self, defaults: Option<&GlobalDownloadSettings>) -> GlobalDownloadSettings {
+        let defaults = defaults.cloned().unwrap_or_default();
+        GlobalDownloadSettings {
+            defaults: self.defaults.or(Some(&defaults.defaults)),
+            max_concurrent: self.max_concurrent.or(defaults.max_concurrent),
+            max_per_host: self.max_per_host.or(defaults.max_per_host),
+            proxy: self.proxy.clone().or(defaults.proxy),
+            no_proxy: self.no_proxy.clone().or(defaults.no_proxy),
+        }
+    }
+
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent.unwrap_or(8).max(1)
+    }
+
+    pub fn max_per_host(&self) -> usize {
+        self.max_per_host.unwrap_or(4).max(1)
+    }
+
+    /// Whether `host` should be reached without the configured `proxy`
+    pub fn bypasses_proxy(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        self.no_proxy.iter().flatten().any(|entry| {
+            let entry = entry.trim().to_lowercase();
+            match entry.strip_prefix('.') {
+                Some(domain) => host == domain || host.ends_with(&entry),
+                None => host == entry || entry == "*",
+            }
+        })
+    }
+}
+
+/// Convert a number of seconds from the configuration to a `Duration`, treating
+/// anything that is not a positive number as zero
+pub(super) fn seconds(value: f64) -> Duration {
+    if value.is_finite() && value > 0.0 {
+        Duration::from_secs_f64(value)
+    } else {
+        Duration::from_secs(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_format_from_file_name() {
+        assert_eq!(
+            ArchiveFormat::from_file_name("nvim-linux64.tar.gz"),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(
+            ArchiveFormat::from_file_name("tool.TXZ"),
+            Some(ArchiveFormat::TarXz)
+        );
+        assert_eq!(
+            ArchiveFormat::from_file_name("fonts.zip"),
+            Some(ArchiveFormat::Zip)
+        );
+        assert_eq!(ArchiveFormat::from_file_name("install.sh"), None);
+    }
+
+    #[test]
+    fn test_extract_definition_from_config() {
+        let definition: FileDownloadDefinition = serde_yaml::from_str(
+            "source: https://example.com/a\ntarget: a.bin\nextract:\n  dest: tools\n  format: tar.xz\n  strip_components: 1\n  overwrite: skip\n",
+        )
+        .unwrap();
+        let extract = definition.extract.unwrap();
+        assert_eq!(extract.format, Some(ArchiveFormat::TarXz));
+        assert_eq!(extract.strip_components, 1);
+        assert_eq!(extract.overwrite, OverwritePolicy::Skip);
+        assert_eq!(
+            extract.destination(Path::new("/opt")),
+            PathBuf::from("/opt/tools")
+        );
+    }
+
+    #[test]
+    fn test_file_mode_from_config() {
+        let modes: Vec<FileMode> =
+            serde_json::from_str(r#"["0755", "644", 700, "0o4755"]"#).unwrap();
+        assert_eq!(
+            modes,
+            vec![
+                FileMode(0o755),
+                FileMode(0o644),
+                FileMode(0o700),
+                FileMode(0o4755)
+            ]
+        );
+        assert_eq!(FileMode(0o755).to_string(), "0755");
+
+        for invalid in &[r#""0789""#, "17777", r#""rwx""#] {
+            assert!(serde_json::from_str::<FileMode>(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn test_download_settings_defaults() {
+        let settings = DownloadSettings::default();
+        assert_eq!(settings.retries(), 2);
+        assert_eq!(settings.retry_delay(), Duration::from_secs(1));
+        assert_eq!(settings.connect_timeout(), Duration::from_secs(30));
+        assert_eq!(settings.read_timeout(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_download_settings_fall_back_to_global() {
+        let global = DownloadSettings {
+            retries: Some(5),
+            read_timeout: Some(-1.0),
+            ..DownloadSettings::default()
+        };
+        let operation = DownloadSettings {
+            retries: Some(0),
+            retry_delay: Some(0.25),
+            ..DownloadSettings::default()
+        };
+
+        let settings = operation.or(Some(&global));
+        assert_eq!(settings.retries(), 0);
+        assert_eq!(settings.retry_delay(), Duration::from_millis(250));
+        assert_eq!(settings.read_timeout(), Duration::from_secs(0));
+        assert_eq!(operation.or(None), operation);
+    }
+
+    #[test]
+    fn test_operation_settings_are_read_inline() {
+        let operation: FileDownloadOperation =
+            serde_json::from_str(r#"{ "files": [], "retries": 4, "read_timeout": 10 }"#).unwrap();
+        assert_eq!(operation.settings.retries, Some(4));
+        assert_eq!(operation.settings.read_timeout(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_github_release_source() {
+        let definition: FileDownloadDefinition = serde_yaml::from_str(
+            "target: rg.tar.gz\ngithub_release:\n  repo: BurntSushi/ripgrep\n  asset: ripgrep-*-{arch}-unknown-{os}-musl.tar.gz\n",
+        )
+        .unwrap();
+        assert!(definition.validate().is_ok());
+        let release = definition.github_release.as_ref().unwrap();
+        assert_eq!(release.tag, "latest");
+        assert_eq!(
+            release.to_string(),
+            "github:BurntSushi/ripgrep@latest ripgrep-*-{arch}-unknown-{os}-musl.tar.gz"
+        );
+
+        let both = FileDownloadDefinition {
+            source: String::from("https://example.com/rg.tar.gz"),
+            ..definition.clone()
+        };
+        assert!(both.validate().is_err());
+        let neither = FileDownloadDefinition {
+            github_release: None,
+            ..definition
+        };
+        assert!(neither.validate().is_err());
+    }
+
+    #[test]
+    fn test_local_sources() {
+        let mut definition: FileDownloadDefinition =
+            serde_json::from_str(r#"{ "source": "assets/font.ttf", "target": "font.ttf" }"#)
+                .unwrap();
+        assert_eq!(
+            definition.local_source().unwrap(),
+            Some(PathBuf::from("assets/font.ttf"))
+        );
+        definition.config_dir = Some(PathBuf::from("/media/usb"));
+        assert_eq!(
+            definition.local_source().unwrap(),
+            Some(PathBuf::from("/media/usb/assets/font.ttf"))
+        );
+
+        definition.source = String::from("file:///srv/mirror/font%20bold.ttf");
+        assert_eq!(
+            definition.local_source().unwrap(),
+            Some(PathBuf::from("/srv/mirror/font bold.ttf"))
+        );
+        definition.source = String::from("file://mirror.local/font.ttf");
+        assert!(definition.local_source().is_err());
+        definition.source = String::from("https://example.com/font.ttf");
+        assert_eq!(definition.local_source().unwrap(), None);
+    }
+
+    #[test]
+    fn test_global_download_settings() {
+        let settings: GlobalDownloadSettings = serde_yaml::from_str(
+            "retries: 3\nmax_concurrent: 0\nproxy: http://proxy:3128\nno_proxy: [localhost, .internal]\n",
+        )
+        .unwrap();
+        assert_eq!(settings.defaults.retries(), 3);
+        assert_eq!(settings.max_concurrent(), 1);
+        assert_eq!(settings.max_per_host(), 4);
+        assert!(settings.bypasses_proxy("localhost"));
+        assert!(settings.bypasses_proxy("mirror.internal"));
+        assert!(settings.bypasses_proxy("Internal"));
+        assert!(!settings.bypasses_proxy("github.com"));
+        assert!(!settings.bypasses_proxy("notinternal"));
+
+        let merged = GlobalDownloadSettings {
+            max_per_host: Some(2),
+            ..GlobalDownloadSettings::default()
+        }
+        .or(Some(&settings));
+        assert_eq!(merged.max_per_host(), 2);
+        assert_eq!(merged.proxy, settings.proxy);
+        assert_eq!(merged.defaults.retries(), 3);
+    }
+
+    #[test]
+    fn test_full_definition_serializes_to_toml() {
+        let definition: FileDownloadDefinition = serde_yaml::from_str(
+            "github_release: { repo: neovim/neovim, asset: nvim.appimage }\ntarget: nvim\nsha256: abc123\nmode: \"0755\"\nowner: root\nextract: { dest: tools }\n",
+        )
+        .unwrap();
+        let toml = toml::to_string_pretty(&definition).unwrap();
+        let actual: FileDownloadDefinition = toml::from_str(&toml).unwrap();
+        assert_eq!(toml::to_string_pretty(&actual).unwrap(), toml);
+        assert_eq!(actual.github_release.unwrap().repo, "neovim/neovim");
+    }
+
+    #[test]
+    fn test_full_operation_serializes_to_toml() {
+        let operation: FileDownloadOperation = serde_yaml::from_str(
+            "base_dir: ~/bin\nretries: 3\nread_timeout: 10\nafter_complete: { command: ls }\nfiles: [{ source: https://example.com/a, target: a }]\nwhen: { os: arch }\nheaders: { Accept: text/plain }\n",
+        )
+        .unwrap();
+        let toml = toml::to_string_pretty(&operation).unwrap();
+        let actual: FileDownloadOperation = toml::from_str(&toml).unwrap();
+        assert_eq!(toml::to_string_pretty(&actual).unwrap(), toml);
+        assert_eq!(actual.settings.retries, Some(3));
+    }
 }
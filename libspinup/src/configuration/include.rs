@@ -0,0 +1,400 @@
+//! The `include` module loads a configuration file along with every file
+//! listed in its `include` section, and merges them into a single
+//! [`Configuration`](../struct.Configuration.html).
+//!
+//! Included files are merged in the order they are listed, and the including
+//! file is merged last, so it takes precedence over anything it includes.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+
+use super::{Configuration, PackageList, SnapChannel, SnapPackage, Snaps};
+
+/// Read the configuration file at `config_path` along with all of its includes
+pub(super) fn read_with_includes(config_path: &Path) -> Result<Configuration> {
+    IncludeLoader::default().load(&canonical(config_path)?)
+}
+
+/// Keeps track of the files being loaded, to catch include cycles
+#[derive(Debug, Default)]
+struct IncludeLoader {
+    /// The chain of files currently being loaded, from the top level config down
+    stack: Vec<PathBuf>,
+
+    /// Every file that has already been loaded
+    loaded: HashSet<PathBuf>,
+}
+
+impl IncludeLoader {
+    /// Load the file at the canonical `target` path and merge in everything it includes
+    fn load(&mut self, target: &Path) -> Result<Configuration> {
+        if self.stack.iter().any(|p| p == target) {
+            let chain: Vec<String> = self
+                .stack
+                .iter()
+                .map(|p| p.as_path())
+                .chain(std::iter::once(target))
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(Error::from(format!(
+                "Config files include each other: {}",
+                chain.join(" -> ")
+            )));
+        }
+        self.loaded.insert(target.to_path_buf());
+
+        let mut config = super::read_config_file(target)?;
+        let includes = config.include.take().unwrap_or_default();
+        let base_dir = target.parent().map(Path::to_path_buf).unwrap_or_default();
+        for operation in config.file_downloads.iter_mut().flatten() {
+            for file in &mut operation.files {
+                file.config_dir = Some(base_dir.clone());
+            }
+        }
+
+        self.stack.push(target.to_path_buf());
+        let mut merged: Option<Configuration> = None;
+        for include in includes {
+            let include_path = canonical(&base_dir.join(include))?;
+            // Files shared by several includes are only merged the first time
+            if self.loaded.contains(&include_path) && !self.stack.contains(&include_path) {
+                debug!("{} was already included, skipping", include_path.display());
+                continue;
+            }
+
+            let included = self.load(&include_path)?;
+            merged = Some(match merged {
+                Some(base) => merge(base, included)?,
+                None => included,
+            });
+        }
+        self.stack.pop();
+
+        Ok(match merged {
+            Some(base) => merge(base, config)?,
+            None => config,
+        })
+    }
+}
+
+fn canonical(path: &Path) -> Result<PathBuf> {
+    path.canonicalize()
+        .map_err(|e| Error::from(format!("Unable to read {}: {}", path.display(), e)))
+}
+
+/// Merge `overlay` on top of `base`.
+///
+/// Lists are appended with the `base` entries first, a command set in `overlay`
+/// replaces any set in `base` with the same name, and flags are set when either
+/// file sets them. Variables and the package manager in `overlay` replace those in `base`.
+fn merge(base: Configuration, overlay: Configuration) -> Result<Configuration> {
+    let command_sets = match (base.command_sets, overlay.command_sets) {
+        (Some(base_sets), Some(overlay_sets)) => {
+            let mut sets: Vec<_> = base_sets
+                .into_iter()
+                .filter(|set| !overlay_sets.iter().any(|o| o.name == set.name))
+                .collect();
+            sets.extend(overlay_sets);
+            Some(sets)
+        }
+        (base_sets, overlay_sets) => overlay_sets.or(base_sets),
+    };
+
+    let snaps = match (base.snaps, overlay.snaps) {
+        (Some(base_snaps), Some(overlay_snaps)) => Some(merge_snaps(base_snaps, overlay_snaps)?),
+        (base_snaps, overlay_snaps) => overlay_snaps.or(base_snaps),
+    };
+
+    Ok(Configuration {
+        include: None,
+        vars: merge_options(base.vars, overlay.vars, |mut base, overlay| {
+            base.extend(overlay);
+            base
+        }),
+        update_system: base.update_system || overlay.update_system,
+        package_list: merge_options(base.package_list, overlay.package_list, merge_packages),
+        file_downloads: merge_options(base.file_downloads, overlay.file_downloads, append),
+        download_settings: merge_options(
+            base.download_settings,
+            overlay.download_settings,
+            |base, overlay| overlay.or(Some(&base)),
+        ),
+        snaps,
+        custom_commands: merge_options(base.custom_commands, overlay.custom_commands, append),
+        command_sets,
+        continue_on_error: base.continue_on_error || overlay.continue_on_error,
+        package_manager: overlay.package_manager.or(base.package_manager),
+        system_details: overlay.system_details,
+    })
+}
+
+fn merge_options<T>(base: Option<T>, overlay: Option<T>, combine: fn(T, T) -> T) -> Option<T> {
+    match (base, overlay) {
+        (Some(base), Some(overlay)) => Some(combine(base, overlay)),
+        (base, overlay) => overlay.or(base),
+    }
+}
+
+fn append<T>(mut base: Vec<T>, overlay: Vec<T>) -> Vec<T> {
+    base.extend(overlay);
+    base
+}
+
+/// Append `overlay` to `base`, leaving out anything already in `base`
+fn append_unique(mut base: Vec<String>, overlay: Vec<String>) -> Vec<String> {
+    for item in overlay {
+        if !base.contains(&item) {
+            base.push(item);
+        }
+    }
+    base
+}
+
+fn merge_packages(base: PackageList, overlay: PackageList) -> PackageList {
+    PackageList {
+        base_packages: merge_options(base.base_packages, overlay.base_packages, append_unique),
+        distro_packages: merge_options(base.distro_packages, overlay.distro_packages, append),
+        continue_on_error: base.continue_on_error || overlay.continue_on_error,
+    }
+}
+
+/// Merge the snaps of `overlay` into `base`, keeping any `when` with the snaps it was given for.
+///
+/// When only one of them has a `when`, it is moved onto each of its snaps, with the standard
+/// snaps being installed one at a time. Two different conditions can't be kept apart, as the
+/// standard snaps of both are installed together.
+fn merge_snaps(base: Snaps, overlay: Snaps) -> Result<Snaps> {
+    let (mut base, overlay) = match (&base.when, &overlay.when) {
+        (Some(base_when), Some(overlay_when)) if base_when != overlay_when => {
+            return Err(Error::from(
+                "Included files give different `when` conditions for their snaps, \
+                 set `when` on each of the `alternate_snaps` instead",
+            ))
+        }
+        (Some(_), None) | (None, Some(_)) => (condition_each(base)?, condition_each(overlay)?),
+        _ => (base, overlay),
+    };
+
+    base.standard_snaps.names =
+        append_unique(base.standard_snaps.names, overlay.standard_snaps.names);
+    base.standard_snaps.continue_on_error |= overlay.standard_snaps.continue_on_error;
+    base.alternate_snaps = merge_options(base.alternate_snaps, overlay.alternate_snaps, append);
+    Ok(base)
+}
+
+/// Move the `when` of `snaps` onto each of the snaps in it
+fn condition_each(mut snaps: Snaps) -> Result<Snaps> {
+    let when = match snaps.when.take() {
+        Some(when) => when,
+        None => return Ok(snaps),
+    };
+
+    let continue_on_error = snaps.standard_snaps.continue_on_error;
+    let mut alternate_snaps: Vec<SnapPackage> = snaps
+        .standard_snaps
+        .names
+        .drain(..)
+        .map(|name| SnapPackage {
+            name,
+            classic: false,
+            channel: SnapChannel::Stable,
+            continue_on_error,
+            when: Some(when.clone()),
+        })
+        .collect();
+    for mut snap in snaps.alternate_snaps.take().unwrap_or_default() {
+        match &snap.when {
+            Some(own) if *own != when => {
+                return Err(Error::from(format!(
+                    "The snap {} has a `when` of its own, so the `when` of its snaps \
+                     can't be kept when merging in an included file",
+                    snap.name
+                )))
+            }
+            _ => snap.when = Some(when.clone()),
+        }
+        alternate_snaps.push(snap);
+    }
+
+    snaps.alternate_snaps = Some(alternate_snaps);
+    Ok(snaps)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn write(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_includes_are_merged_before_the_including_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("roles")).unwrap();
+        write(
+            dir.path(),
+            "base.toml",
+            r#"
+[package_list]
+base_packages = ["git", "vim"]
+
+[[custom_commands]]
+command = "echo"
+args = ["base"]
+"#,
+        );
+        write(
+            &dir.path().join("roles"),
+            "laptop.json",
+            r#"{
+                "include": ["../base.toml"],
+                "vars": { "role": "base" },
+                "package_list": { "base_packages": ["tlp"] },
+                "command_sets": [{ "name": "dots", "commands": [{ "id": 1, "command": "ls" }] }]
+            }"#,
+        );
+        let top = write(
+            dir.path(),
+            "config.yml",
+            r#"
+include:
+  - roles/laptop.json
+vars:
+  role: laptop
+update_system: true
+package_list:
+  base_packages: [vim, tmux]
+custom_commands:
+  - command: echo
+    args: [top]
+command_sets:
+  - name: dots
+    commands:
+      - id: 1
+        command: pwd
+"#,
+        );
+
+        let config = read_with_includes(&top).unwrap();
+        assert!(config.update_system);
+        assert!(config.include.is_none());
+        assert_eq!(config.vars.unwrap()["role"], "laptop");
+        assert_eq!(
+            config.package_list.unwrap().base_packages.unwrap(),
+            vec!["git", "vim", "tlp", "tmux"]
+        );
+        let args: Vec<_> = config
+            .custom_commands
+            .unwrap()
+            .into_iter()
+            .map(|c| c.args.unwrap())
+            .collect();
+        assert_eq!(args, vec![vec!["base"], vec!["top"]]);
+        let sets = config.command_sets.unwrap();
+        assert_eq!(sets.len(), 1);
+        assert_eq!(sets[0].commands[0].command.command, "pwd");
+    }
+
+    #[test]
+    fn test_snap_conditions_stay_with_their_own_snaps() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "base.yml",
+            "snaps:\n  standard_snaps:\n    names: [spotify]\n",
+        );
+        write(
+            dir.path(),
+            "laptop.yml",
+            "snaps:\n  when: { hostname: laptop }\n  standard_snaps:\n    names: [slack]\n  alternate_snaps:\n    - { name: code, classic: true }\n",
+        );
+        let top = write(dir.path(), "top.yml", "include: [base.yml, laptop.yml]\n");
+
+        let snaps = read_with_includes(&top).unwrap().snaps.unwrap();
+        assert_eq!(snaps.when, None);
+        assert_eq!(snaps.standard_snaps.names, vec!["spotify"]);
+        let alternate = snaps.alternate_snaps.unwrap();
+        let names: Vec<&str> = alternate.iter().map(|s| &s.name[..]).collect();
+        assert_eq!(names, vec!["slack", "code"]);
+        assert!(alternate
+            .iter()
+            .all(|s| s.when.as_ref().unwrap().hostname.as_deref() == Some("laptop")));
+
+        write(
+            dir.path(),
+            "desktop.yml",
+            "snaps:\n  when: { hostname: desktop }\n  standard_snaps:\n    names: [steam]\n",
+        );
+        write(
+            dir.path(),
+            "both.yml",
+            "include: [laptop.yml, desktop.yml]\n",
+        );
+        let err = read_with_includes(&dir.path().join("both.yml"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("different `when` conditions"));
+    }
+
+    #[test]
+    fn test_include_cycle_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = write(dir.path(), "first.yml", "include: [second.yml]\n");
+        write(dir.path(), "second.yml", "include: [first.yml]\n");
+
+        let err = read_with_includes(&first).unwrap_err().to_string();
+        assert!(err.starts_with("Config files include each other"));
+        assert!(err.ends_with("first.yml"));
+    }
+
+    #[test]
+    fn test_shared_include_only_merged_once() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            "common.yml",
+            "custom_commands:\n  - command: ls\n",
+        );
+        write(dir.path(), "a.yml", "include: [common.yml]\n");
+        write(dir.path(), "b.yml", "include: [common.yml]\n");
+        let top = write(dir.path(), "top.yml", "include: [a.yml, b.yml]\n");
+
+        let config = read_with_includes(&top).unwrap();
+        assert_eq!(config.custom_commands.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_missing_include_names_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let top = write(dir.path(), "top.yml", "include: [missing.yml]\n");
+
+        let err = read_with_includes(&top).unwrap_err().to_string();
+        assert!(err.contains("missing.yml"));
+    }
+
+    #[test]
+    fn test_local_sources_are_relative_to_their_own_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("roles")).unwrap();
+        write(
+            &dir.path().join("roles"),
+            "fonts.yml",
+            "file_downloads:\n  - files:\n      - { source: fonts/a.ttf, target: a.ttf }\n",
+        );
+        let top = write(dir.path(), "top.yml", "include: [roles/fonts.yml]\n");
+
+        let config = read_with_includes(&top).unwrap();
+        let file = &config.file_downloads.unwrap()[0].files[0];
+        assert_eq!(
+            file.local_source().unwrap(),
+            Some(canonical(dir.path()).unwrap().join("roles/fonts/a.ttf"))
+        );
+    }
+}
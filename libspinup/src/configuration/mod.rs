@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::env;
 use std::fs::File;
 use std::io::Read;
@@ -7,15 +8,21 @@ use serde::{Deserialize, Serialize};
 
 use crate::error::{Error, Result};
 
+mod auth;
 mod command;
 mod commandset;
+mod condition;
 mod files;
+mod include;
 mod packages;
 mod snap;
 mod system;
+mod vars;
 
+pub use auth::*;
 pub use command::*;
 pub use commandset::*;
+pub use condition::*;
 pub use files::*;
 pub use packages::*;
 pub use snap::*;
@@ -24,10 +31,21 @@ pub use system::*;
 /// Main configuration struct
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Configuration {
+    /// Other configuration files to merge into this one, relative to this file
+    pub include: Option<Vec<String>>,
+
     /// Whether to run a system update _before_ executing other operations
     #[serde(default)]
     pub update_system: bool,
 
+    /// Whether the run should carry on when any operation fails, reporting
+    /// all of the failures at the end
+    #[serde(default)]
+    pub continue_on_error: bool,
+
+    /// Variables that can be referenced as `${name}` in command arguments and file downloads
+    pub vars: Option<BTreeMap<String, String>>,
+
     /// An optional list of [`PackageList`](struct.PackageList.html) items to install
     pub package_list: Option<PackageList>,
 
@@ -35,6 +53,10 @@ pub struct Configuration {
     /// to download
     pub file_downloads: Option<Vec<FileDownloadOperation>>,
 
+    /// The default [`DownloadSettings`](struct.DownloadSettings.html) for every file download,
+    /// along with the limits and proxy that all downloads share
+    pub download_settings: Option<GlobalDownloadSettings>,
+
     /// An optional list of [`Snaps`](struct.Snaps.html) to install
     pub snaps: Option<Snaps>,
 
@@ -43,6 +65,10 @@ pub struct Configuration {
 
     pub command_sets: Option<Vec<CommandSet>>,
 
+    /// An optional [`PackageManagerDefinition`](struct.PackageManagerDefinition.html) that
+    /// overrides the built-in package manager for this system
+    pub package_manager: Option<PackageManagerDefinition>,
+
     /// The current system details when this configuration was created
     #[serde(skip, default = "SystemDetails::default")]
     pub system_details: SystemDetails,
@@ -57,6 +83,9 @@ pub(crate) trait Validatable {
 /// Read in the configuration file specified by `config_path` and parse its contents
 /// into a `Configuration` instance.
 ///
+/// Any files listed in its `include` section are read in as well, and merged in
+/// before the contents of this file.
+///
 /// # Arguments:
 /// - `config_path`: The path to the target configuration file
 ///
@@ -68,8 +97,36 @@ pub(crate) trait Validatable {
 /// - The specified path does not exist
 /// - The specified file cannot be read
 /// - The specified file cannot be parsed as toml, yaml, or json
+/// - An included file cannot be read or parsed, or files include each other
 pub fn read_in_config(config_path: &str) -> Result<Configuration> {
-    PathBuf::from(config_path).canonicalize().map(|target| {
+    read_in_config_with_vars(config_path, &BTreeMap::new())
+}
+
+/// Read in the configuration file specified by `config_path`, the same as
+/// [`read_in_config`](fn.read_in_config.html), with `vars` taking precedence
+/// over the variables defined in the file.
+///
+/// # Errors:
+/// Along with the errors from [`read_in_config`](fn.read_in_config.html), this will
+/// return an error when a value references a variable that is not defined.
+pub fn read_in_config_with_vars(
+    config_path: &str,
+    vars: &BTreeMap<String, String>,
+) -> Result<Configuration> {
+    let mut config = include::read_with_includes(Path::new(config_path))?;
+    vars::substitute_vars(&mut config, vars)?;
+
+    if let Some(package_manager) = &config.package_manager {
+        config.system_details = config
+            .system_details
+            .with_package_manager(package_manager.clone());
+    }
+
+    Ok(config)
+}
+
+fn read_config_file(config_path: &Path) -> Result<Configuration> {
+    config_path.canonicalize().map(|target| {
         if !target.is_file() {
             return Err(format!("{:?} is not a file", target).into());
         }
@@ -90,6 +147,11 @@ impl Validatable for Configuration {
                 command.validate()?;
             }
         }
+        if let Some(operations) = &self.file_downloads {
+            for file in operations.iter().flat_map(|op| &op.files) {
+                file.validate()?;
+            }
+        }
         Ok(())
     }
 }
@@ -320,6 +382,50 @@ packages:
         assert!(actual.is_ok());
     }
 
+    #[test]
+    fn test_toml_round_trip() {
+        for sample in &["../examples/sample.json", "../examples/sample.yml"] {
+            let config = read_in_config(sample).unwrap();
+            let toml = toml::to_string_pretty(&config).unwrap();
+            let actual = parse_file_contents(toml.clone(), FileSyntax::Toml).unwrap();
+            assert_eq!(toml::to_string_pretty(&actual).unwrap(), toml);
+        }
+    }
+
+    #[test]
+    fn test_vars_serialize_to_toml() {
+        let config = parse_file_contents(
+            String::from("vars:\n  version: \"1.2\"\nupdate_system: true\n"),
+            FileSyntax::Yaml,
+        )
+        .unwrap();
+        let toml = toml::to_string_pretty(&config).unwrap();
+        let actual = parse_file_contents(toml.clone(), FileSyntax::Toml).unwrap();
+        assert!(actual.update_system);
+        assert_eq!(actual.vars, config.vars);
+    }
+
+    #[test]
+    fn test_reading_in_custom_package_manager() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yml");
+        std::fs::write(
+            &path,
+            "package_manager:\n  binary: nala\n  needs_root: true\n",
+        )
+        .unwrap();
+
+        let actual = read_in_config(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            actual.package_manager.as_ref().unwrap().binary,
+            Some(String::from("nala"))
+        );
+        assert_eq!(
+            actual.system_details.package_manager().name(),
+            Some(String::from("nala"))
+        );
+    }
+
     #[test]
     fn test_reading_fail_on_dir() {
         let actual = read_in_config("/tmp");
@@ -341,12 +447,17 @@ packages:
     #[test]
     fn test_validate_with_no_command_sets() {
         let config = Configuration {
+            include: None,
+            vars: None,
             package_list: None,
             file_downloads: None,
+            download_settings: None,
             snaps: None,
             custom_commands: None,
             command_sets: None,
             update_system: false,
+            continue_on_error: false,
+            package_manager: None,
             system_details: SystemDetails::default(),
         };
         assert!(config.validate().is_ok());
@@ -355,8 +466,11 @@ packages:
     #[test]
     fn test_validate_with_ok_command_sets() {
         let config = Configuration {
+            include: None,
+            vars: None,
             package_list: None,
             file_downloads: None,
+            download_settings: None,
             snaps: None,
             custom_commands: None,
             command_sets: Some(vec![CommandSet {
@@ -365,8 +479,11 @@ packages:
                     id: 1,
                     command: CustomCommand::new("ls".to_string(), None, false),
                 }],
+                when: None,
             }]),
             update_system: false,
+            continue_on_error: false,
+            package_manager: None,
             system_details: SystemDetails::default(),
         };
         assert!(config.validate().is_ok());
@@ -375,8 +492,11 @@ packages:
     #[test]
     fn test_validate_with_err_command_sets() {
         let config = Configuration {
+            include: None,
+            vars: None,
             package_list: None,
             file_downloads: None,
+            download_settings: None,
             snaps: None,
             custom_commands: None,
             command_sets: Some(vec![CommandSet {
@@ -391,8 +511,11 @@ packages:
                         command: CustomCommand::new("ls".to_string(), None, false),
                     },
                 ],
+                when: None,
             }]),
             update_system: false,
+            continue_on_error: false,
+            package_manager: None,
             system_details: SystemDetails::default(),
         };
         assert!(config.validate().is_err());
@@ -7,7 +7,7 @@ use super::{SystemDetails, TargetOperatingSystem};
 
 /// `DistroPackages` refer to collections of packages that may only
 /// exist on individual distributions or that have differing names.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DistroPackages {
     /// The target OS that these packages should install on, such as:
     /// - debian
@@ -35,12 +35,16 @@ impl DistroPackages {
 /// via the distro's package manager. This is split into common ones
 /// that usually have the same name across distros and distro specific
 /// packages whose names vary across distributions.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct PackageList {
     /// A collection of common packages that have the same name across distributions
     /// such as vim, git, etc.
     pub base_packages: Option<Vec<String>>,
 
+    /// Whether the run should carry on if the package install fails
+    #[serde(default)]
+    pub continue_on_error: bool,
+
     /// Packages that may only exist on particular distros, or those whose names
     /// change across distributions.
     pub distro_packages: Option<Vec<DistroPackages>>,
@@ -57,7 +61,7 @@ impl PackageList {
 
     /// Helper that will indicate whether there are packages listed for the current Linux
     /// distribution in `distro_packages`
-    fn has_distro_packages(&self, system_details: SystemDetails) -> bool {
+    fn has_distro_packages(&self, system_details: &SystemDetails) -> bool {
         match &self.distro_packages {
             Some(pkgs) => pkgs.iter().any(|it| {
                 TargetOperatingSystem::from(&it.target_os[..]) == system_details.current_os()
@@ -68,12 +72,46 @@ impl PackageList {
     }
 }
 
+impl PackageList {
+    /// All of the packages that should be installed on the current system, which
+    /// is the `base_packages` followed by the matching `distro_packages`
+    pub fn packages_for(&self, system_details: &SystemDetails) -> Vec<String> {
+        let mut packages = self.base_packages.clone().unwrap_or_default();
+
+        if let Some(distro_packages) = &self.distro_packages {
+            if let Some(package_def) = distro_packages.iter().find(|it| {
+                TargetOperatingSystem::from(&it.target_os[..]) == system_details.current_os()
+                    && it.has_packages()
+            }) {
+                if let Some(distro) = &package_def.packages {
+                    packages.extend(distro.clone());
+                }
+            }
+        }
+
+        packages
+    }
+
+    /// Create a copy of this list that will install only the given `packages`
+    pub fn with_only(&self, packages: Vec<String>) -> PackageList {
+        PackageList {
+            base_packages: Some(packages),
+            distro_packages: None,
+            continue_on_error: self.continue_on_error,
+        }
+    }
+}
+
 impl RunnableOperation for PackageList {
-    fn needs_root(&self) -> bool {
-        true
+    fn needs_root(&self, system_details: &SystemDetails) -> bool {
+        system_details.package_manager().needs_root()
+    }
+
+    fn continue_on_error(&self) -> bool {
+        self.continue_on_error
     }
 
-    fn command_name(&self, system_details: SystemDetails) -> Result<String> {
+    fn command_name(&self, system_details: &SystemDetails) -> Result<String> {
         if !self.has_base_packages() && !self.has_distro_packages(system_details) {
             return Err(Error::from(
                 "Packages present in config, but no packages were listed",
@@ -85,7 +123,7 @@ impl RunnableOperation for PackageList {
         })
     }
 
-    fn args(&self, system_details: SystemDetails) -> Option<Vec<String>> {
+    fn args(&self, system_details: &SystemDetails) -> Option<Vec<String>> {
         if !self.has_base_packages() && !self.has_distro_packages(system_details) {
             return None;
         }
@@ -104,20 +142,7 @@ impl RunnableOperation for PackageList {
             install_args.push(autoconfirm);
         }
 
-        if let Some(packages) = &self.base_packages {
-            install_args.extend(packages.clone());
-        }
-
-        if let Some(distro_packages) = &self.distro_packages {
-            if let Some(package_def) = distro_packages.iter().find(|it| {
-                TargetOperatingSystem::from(&it.target_os[..]) == system_details.current_os()
-                    && it.has_packages()
-            }) {
-                if let Some(packages) = &package_def.packages {
-                    install_args.extend(packages.clone());
-                }
-            }
-        }
+        install_args.extend(self.packages_for(system_details));
 
         Some(install_args)
     }
@@ -133,9 +158,10 @@ mod tests {
         let package_list = PackageList {
             base_packages: None,
             distro_packages: None,
+            continue_on_error: false,
         };
 
-        assert!(package_list.needs_root());
+        assert!(package_list.needs_root(&SystemDetails::default()));
     }
 
     #[test]
@@ -143,9 +169,10 @@ mod tests {
         let package_list = PackageList {
             base_packages: Some(vec![String::from("git")]),
             distro_packages: None,
+            continue_on_error: false,
         };
         let actual_res =
-            package_list.command_name(SystemDetails::new(TargetOperatingSystem::Unknown));
+            package_list.command_name(&SystemDetails::new(TargetOperatingSystem::Unknown));
         assert!(actual_res.is_err());
     }
 
@@ -154,9 +181,10 @@ mod tests {
         let package_list = PackageList {
             base_packages: None,
             distro_packages: None,
+            continue_on_error: false,
         };
         let actual_res =
-            package_list.command_name(SystemDetails::new(TargetOperatingSystem::Debian));
+            package_list.command_name(&SystemDetails::new(TargetOperatingSystem::Debian));
         assert!(actual_res.is_err());
     }
 
@@ -168,8 +196,10 @@ mod tests {
                 target_os: String::from("manjaro"),
                 packages: None,
             }]),
+            continue_on_error: false,
         };
-        let actual_res = package_list.command_name(SystemDetails::new(TargetOperatingSystem::Arch));
+        let actual_res =
+            package_list.command_name(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_res.is_err());
     }
 
@@ -181,9 +211,10 @@ mod tests {
                 target_os: String::from("ubuntu"),
                 packages: Some(vec![]),
             }]),
+            continue_on_error: false,
         };
         let actual_res =
-            package_list.command_name(SystemDetails::new(TargetOperatingSystem::Debian));
+            package_list.command_name(&SystemDetails::new(TargetOperatingSystem::Debian));
         assert!(actual_res.is_err());
     }
 
@@ -192,9 +223,10 @@ mod tests {
         let package_list = PackageList {
             base_packages: Some(vec![String::from("git")]),
             distro_packages: None,
+            continue_on_error: false,
         };
         let actual_res =
-            package_list.command_name(SystemDetails::new(TargetOperatingSystem::Debian));
+            package_list.command_name(&SystemDetails::new(TargetOperatingSystem::Debian));
         assert!(actual_res.is_ok());
         let actual = actual_res.unwrap();
         assert_eq!(actual, String::from("apt-get"));
@@ -208,10 +240,51 @@ mod tests {
                 target_os: String::from("arch"),
                 packages: Some(vec![String::from("git")]),
             }]),
+            continue_on_error: false,
         };
-        let actual_res = package_list.command_name(SystemDetails::new(TargetOperatingSystem::Arch));
+        let actual_res =
+            package_list.command_name(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_res.is_ok());
         let actual = actual_res.unwrap();
         assert_eq!(actual, String::from("pacman"));
     }
+
+    #[test]
+    fn test_packages_for_base_and_matching_distro() {
+        let package_list = PackageList {
+            base_packages: Some(vec![String::from("git")]),
+            distro_packages: Some(vec![
+                DistroPackages {
+                    target_os: String::from("ubuntu"),
+                    packages: Some(vec![String::from("pcscd")]),
+                },
+                DistroPackages {
+                    target_os: String::from("arch"),
+                    packages: Some(vec![String::from("bat")]),
+                },
+            ]),
+            continue_on_error: false,
+        };
+        let actual = package_list.packages_for(&SystemDetails::new(TargetOperatingSystem::Arch));
+        assert_eq!(actual, vec![String::from("git"), String::from("bat")]);
+    }
+
+    #[test]
+    fn test_with_only_replaces_packages() {
+        let package_list = PackageList {
+            base_packages: Some(vec![String::from("git"), String::from("vim")]),
+            distro_packages: None,
+            continue_on_error: true,
+        };
+        let actual = package_list.with_only(vec![String::from("vim")]);
+        assert_eq!(
+            actual.args(&SystemDetails::new(TargetOperatingSystem::Arch)),
+            Some(vec![
+                String::from("-S"),
+                String::from("--noconfirm"),
+                String::from("vim")
+            ])
+        );
+        assert!(actual.continue_on_error);
+    }
 }
@@ -11,7 +11,7 @@ use serde::{Deserialize, Serialize};
 use crate::error::{Error, Result};
 use crate::operations::RunnableOperation;
 
-use super::SystemDetails;
+use super::{Condition, SystemDetails};
 
 /// Represents a channel that a snap can be installed from. This does
 /// not guarantee that a snap can be installed from the given channel,
@@ -66,6 +66,13 @@ pub struct SnapPackage {
     /// The channel from which to install this snap, defaults to [`SnapChannel::Stable`](enum.SnapChannel.html#variant.Stable)
     #[serde(default)]
     pub channel: SnapChannel,
+
+    /// Whether the run should carry on if this snap fails to install
+    #[serde(default)]
+    pub continue_on_error: bool,
+
+    /// Only install this snap when the condition holds on the current system
+    pub when: Option<Condition>,
 }
 
 /// A container for a set of snaps that can all be installed from the
@@ -74,17 +81,21 @@ pub struct SnapPackage {
 pub struct StandardSnaps {
     /// The names of the snaps to install
     pub names: Vec<String>,
+
+    /// Whether the run should carry on if these snaps fail to install
+    #[serde(default)]
+    pub continue_on_error: bool,
 }
 
 impl RunnableOperation for SnapPackage {
-    fn command_name(&self, _system_details: SystemDetails) -> Result<String> {
+    fn command_name(&self, _system_details: &SystemDetails) -> Result<String> {
         match self.name.len() {
             0 => Err(Error::from("Cannot install a snap with no name")),
             _ => Ok(String::from("snap")),
         }
     }
 
-    fn args(&self, _system_details: SystemDetails) -> Option<Vec<String>> {
+    fn args(&self, _system_details: &SystemDetails) -> Option<Vec<String>> {
         let mut args = vec![
             String::from("install"),
             self.name.clone(),
@@ -98,9 +109,19 @@ impl RunnableOperation for SnapPackage {
         Some(args)
     }
 
-    fn needs_root(&self) -> bool {
+    fn needs_root(&self, _system_details: &SystemDetails) -> bool {
         true
     }
+
+    fn continue_on_error(&self) -> bool {
+        self.continue_on_error
+    }
+
+    fn skip_reason(&self, system_details: &SystemDetails) -> Option<String> {
+        self.when
+            .as_ref()
+            .and_then(|when| when.unmet(system_details))
+    }
 }
 
 /// Upper-most container for snap install directives.
@@ -113,10 +134,13 @@ pub struct Snaps {
     /// These snaps need to be installed from other channels and/or
     /// need `--classic` confinement
     pub alternate_snaps: Option<Vec<SnapPackage>>,
+
+    /// Only install any of these snaps when the condition holds on the current system
+    pub when: Option<Condition>,
 }
 
 impl RunnableOperation for StandardSnaps {
-    fn command_name(&self, _system_details: SystemDetails) -> Result<String> {
+    fn command_name(&self, _system_details: &SystemDetails) -> Result<String> {
         if self.names.is_empty() {
             Err(Error::from("Snap list was present but no names were given"))
         } else {
@@ -124,7 +148,7 @@ impl RunnableOperation for StandardSnaps {
         }
     }
 
-    fn args(&self, _system_details: SystemDetails) -> Option<Vec<String>> {
+    fn args(&self, _system_details: &SystemDetails) -> Option<Vec<String>> {
         if self.names.is_empty() {
             None
         } else {
@@ -134,9 +158,13 @@ impl RunnableOperation for StandardSnaps {
         }
     }
 
-    fn needs_root(&self) -> bool {
+    fn needs_root(&self, _system_details: &SystemDetails) -> bool {
         true
     }
+
+    fn continue_on_error(&self) -> bool {
+        self.continue_on_error
+    }
 }
 
 #[cfg(test)]
@@ -156,10 +184,11 @@ mod tests {
     fn test_get_expected_executable_from_standard_snaps() {
         let standard_snaps = StandardSnaps {
             names: vec![String::from("dummy")],
+            continue_on_error: false,
         };
 
         let actual_res =
-            standard_snaps.command_name(SystemDetails::new(TargetOperatingSystem::Arch));
+            standard_snaps.command_name(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_res.is_ok());
         let actual_cmd = actual_res.unwrap();
         assert_eq!(actual_cmd, String::from("snap"));
@@ -169,8 +198,9 @@ mod tests {
     fn test_get_expected_arguments_from_standard_snaps() {
         let standard_snaps = StandardSnaps {
             names: vec![String::from("spotify"), String::from("code")],
+            continue_on_error: false,
         };
-        let actual_opt = standard_snaps.args(SystemDetails::new(TargetOperatingSystem::Arch));
+        let actual_opt = standard_snaps.args(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_opt.is_some());
         let actual_args = actual_opt.unwrap();
         let expected_args = vec![
@@ -183,16 +213,22 @@ mod tests {
 
     #[test]
     fn ensure_no_names_gives_none_args() {
-        let standard_snaps = StandardSnaps { names: vec![] };
-        let actual_opt = standard_snaps.args(SystemDetails::new(TargetOperatingSystem::Arch));
+        let standard_snaps = StandardSnaps {
+            names: vec![],
+            continue_on_error: false,
+        };
+        let actual_opt = standard_snaps.args(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_opt.is_none());
     }
 
     #[test]
     fn ensure_empty_snap_list_is_command_err() {
-        let standard_snaps = StandardSnaps { names: Vec::new() };
+        let standard_snaps = StandardSnaps {
+            names: Vec::new(),
+            continue_on_error: false,
+        };
         let actual_res =
-            standard_snaps.command_name(SystemDetails::new(TargetOperatingSystem::Arch));
+            standard_snaps.command_name(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_res.is_err());
     }
 
@@ -200,8 +236,9 @@ mod tests {
     fn ensure_standard_snaps_needs_root() {
         let standard_snaps = StandardSnaps {
             names: vec![String::from("dummy")],
+            continue_on_error: false,
         };
-        assert!(standard_snaps.needs_root());
+        assert!(standard_snaps.needs_root(&SystemDetails::default()));
     }
 
     #[test]
@@ -210,8 +247,10 @@ mod tests {
             name: String::from("spotify"),
             classic: true,
             channel: SnapChannel::default(),
+            continue_on_error: false,
+            when: None,
         };
-        let actual_res = package.command_name(SystemDetails::new(TargetOperatingSystem::Arch));
+        let actual_res = package.command_name(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_res.is_ok());
         let actual_cmd = actual_res.unwrap();
         assert_eq!(actual_cmd, String::from("snap"));
@@ -223,8 +262,10 @@ mod tests {
             name: String::from("spotify"),
             classic: true,
             channel: SnapChannel::default(),
+            continue_on_error: false,
+            when: None,
         };
-        let actual_res = package.args(SystemDetails::new(TargetOperatingSystem::Arch));
+        let actual_res = package.args(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_res.is_some());
         let actual_args = actual_res.unwrap();
         assert_eq!(
@@ -244,8 +285,10 @@ mod tests {
             name: String::new(),
             classic: true,
             channel: SnapChannel::default(),
+            continue_on_error: false,
+            when: None,
         };
-        let actual_res = package.command_name(SystemDetails::new(TargetOperatingSystem::Arch));
+        let actual_res = package.command_name(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_res.is_err());
     }
 
@@ -255,8 +298,10 @@ mod tests {
             name: String::from("spotify"),
             classic: false,
             channel: SnapChannel::default(),
+            continue_on_error: false,
+            when: None,
         };
-        let actual_res = package.args(SystemDetails::new(TargetOperatingSystem::Arch));
+        let actual_res = package.args(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_res.is_some());
         let actual_args = actual_res.unwrap();
         assert_eq!(
@@ -275,8 +320,10 @@ mod tests {
             name: String::from("spotify"),
             classic: false,
             channel: SnapChannel::Beta,
+            continue_on_error: false,
+            when: None,
         };
-        let actual_res = package.args(SystemDetails::new(TargetOperatingSystem::Arch));
+        let actual_res = package.args(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_res.is_some());
         let actual_args = actual_res.unwrap();
         assert_eq!(
@@ -295,8 +342,10 @@ mod tests {
             name: String::from("spotify"),
             classic: false,
             channel: SnapChannel::Candidate,
+            continue_on_error: false,
+            when: None,
         };
-        let actual_res = package.args(SystemDetails::new(TargetOperatingSystem::Arch));
+        let actual_res = package.args(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_res.is_some());
         let actual_args = actual_res.unwrap();
         assert_eq!(
@@ -315,8 +364,10 @@ mod tests {
             name: String::from("spotify"),
             classic: false,
             channel: SnapChannel::Edge,
+            continue_on_error: false,
+            when: None,
         };
-        let actual_res = package.args(SystemDetails::new(TargetOperatingSystem::Arch));
+        let actual_res = package.args(&SystemDetails::new(TargetOperatingSystem::Arch));
         assert!(actual_res.is_some());
         let actual_args = actual_res.unwrap();
         assert_eq!(
@@ -335,7 +386,9 @@ mod tests {
             name: String::from("spotify"),
             classic: false,
             channel: SnapChannel::Edge,
+            continue_on_error: false,
+            when: None,
         };
-        assert!(package.needs_root());
+        assert!(package.needs_root(&SystemDetails::default()));
     }
 }
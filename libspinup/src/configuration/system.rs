@@ -3,11 +3,14 @@
 //! update & upgrade commands, etc.
 
 use std::collections::HashMap;
+use std::env;
+use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
 use sys_info;
 
 use crate::error::{Error, Result};
-use crate::operations::RunnableOperation;
+use crate::operations::{CommandOutput, RunnableOperation};
 
 lazy_static! {
     static ref OS_MAPPINGS: HashMap<&'static str, TargetOperatingSystem> = {
@@ -22,6 +25,13 @@ lazy_static! {
         h.insert("fedora", TargetOperatingSystem::Fedora);
         h.insert("centos", TargetOperatingSystem::Fedora);
         h.insert("rhel", TargetOperatingSystem::RedHat);
+        h.insert("opensuse", TargetOperatingSystem::OpenSuse);
+        h.insert("opensuse-tumbleweed", TargetOperatingSystem::OpenSuse);
+        h.insert("opensuse-leap", TargetOperatingSystem::OpenSuse);
+        h.insert("suse", TargetOperatingSystem::OpenSuse);
+        h.insert("alpine", TargetOperatingSystem::Alpine);
+        h.insert("void", TargetOperatingSystem::Void);
+        h.insert("gentoo", TargetOperatingSystem::Gentoo);
         h
     };
 }
@@ -39,11 +49,53 @@ pub struct PackageManager {
     /// The command passed to [`name`](struct.PackageManager.html#structfield.name) to update package lists
     update_subcommand: String,
 
-    /// The command passed to [`name`](struct.PackageManager.html#structfield.name) to upgrade system packages
+    /// The command passed to [`name`](struct.PackageManager.html#structfield.name) to upgrade system packages,
+    /// this may be several whitespace separated arguments
     upgrade_subcommand: String,
 
-    /// The argument that will bypass confirmation requests
+    /// The argument that will bypass confirmation requests, empty for package managers
+    /// that never ask
     autoconfirm: String,
+
+    /// The command used to check whether a package is installed (e.g. `pacman`, `rpm`)
+    query_command: String,
+
+    /// The arguments passed to [`query_command`](struct.PackageManager.html#structfield.query_command),
+    /// the package name is added after these
+    query_args: Vec<String>,
+
+    /// Text that must be in the output of a successful query for the package to count as
+    /// installed, for package managers that also know about removed packages
+    installed_marker: String,
+
+    /// Whether the install, update and upgrade commands need to be run via `sudo`
+    needs_root: bool,
+}
+
+/// A package manager declared in the configuration file, used for distros that
+/// spinup doesn't know about or to swap in a wrapper such as `paru` or `nala`.
+///
+/// Any field that is left out falls back to the built-in
+/// [`PackageManager`](struct.PackageManager.html) for the current system.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct PackageManagerDefinition {
+    /// The package manager executable (e.g. `paru`, `nala`)
+    pub binary: Option<String>,
+
+    /// The subcommand used to install packages (e.g. `-S`, `install`)
+    pub install_subcommand: Option<String>,
+
+    /// The subcommand used to update package lists
+    pub update_subcommand: Option<String>,
+
+    /// The subcommand used to upgrade system packages
+    pub upgrade_subcommand: Option<String>,
+
+    /// The argument that will bypass confirmation requests
+    pub autoconfirm: Option<String>,
+
+    /// Whether the package manager needs to be run via `sudo`, defaults to `true`
+    pub needs_root: Option<bool>,
 }
 
 /// Internal struct used to make runnable operations on the fly
@@ -58,6 +110,9 @@ struct SystemRefreshOperation {
 
     /// The autoconfirm argument value
     pub autoconfirm: String,
+
+    /// Whether the command is run via `sudo`
+    pub needs_root: bool,
 }
 
 impl SystemRefreshOperation {
@@ -66,12 +121,13 @@ impl SystemRefreshOperation {
             command_name: command_name.to_string(),
             target_subcommand: target_subcommand.to_string(),
             autoconfirm: autoconfirm.to_string(),
+            needs_root: true,
         }
     }
 }
 
 impl RunnableOperation for SystemRefreshOperation {
-    fn command_name(&self, _system_details: SystemDetails) -> Result<String> {
+    fn command_name(&self, _system_details: &SystemDetails) -> Result<String> {
         if self.command_name.is_empty() {
             Err(Error::from(
                 "Cannot run update/upgrade operations on this platform",
@@ -81,15 +137,44 @@ impl RunnableOperation for SystemRefreshOperation {
         }
     }
 
-    fn args(&self, _system_details: SystemDetails) -> Option<Vec<String>> {
-        Some(vec![
-            self.target_subcommand.clone(),
-            self.autoconfirm.clone(),
-        ])
+    fn args(&self, _system_details: &SystemDetails) -> Option<Vec<String>> {
+        let mut args: Vec<String> = self
+            .target_subcommand
+            .split_whitespace()
+            .map(String::from)
+            .collect();
+        if !self.autoconfirm.is_empty() {
+            args.push(self.autoconfirm.clone());
+        }
+        Some(args)
+    }
+
+    fn needs_root(&self, _system_details: &SystemDetails) -> bool {
+        self.needs_root
     }
+}
 
-    fn needs_root(&self) -> bool {
-        true
+/// Internal struct used to check whether a single package is installed
+#[derive(Debug, Clone)]
+struct PackageQueryOperation {
+    /// The query command, the backing [`query_command`](struct.PackageManager.html#structfield.query_command)
+    pub command_name: String,
+
+    /// The query arguments, ending with the package name
+    pub args: Vec<String>,
+}
+
+impl RunnableOperation for PackageQueryOperation {
+    fn command_name(&self, _system_details: &SystemDetails) -> Result<String> {
+        Ok(self.command_name.clone())
+    }
+
+    fn args(&self, _system_details: &SystemDetails) -> Option<Vec<String>> {
+        Some(self.args.clone())
+    }
+
+    fn needs_root(&self, _system_details: &SystemDetails) -> bool {
+        false
     }
 }
 
@@ -107,7 +192,37 @@ impl PackageManager {
             update_subcommand: update_subcommand.to_string(),
             upgrade_subcommand: upgrade_subcommand.to_string(),
             autoconfirm: autoconfirm.to_string(),
+            query_command: String::new(),
+            query_args: Vec::new(),
+            installed_marker: String::new(),
+            needs_root: true,
+        }
+    }
+
+    /// Replace any part of this package manager that is set in `definition`
+    fn with_definition(mut self, definition: &PackageManagerDefinition) -> Self {
+        let replace = |field: &mut String, value: &Option<String>| {
+            if let Some(value) = value {
+                *field = value.clone();
+            }
+        };
+        replace(&mut self.name, &definition.binary);
+        replace(&mut self.install_subcommand, &definition.install_subcommand);
+        replace(&mut self.update_subcommand, &definition.update_subcommand);
+        replace(&mut self.upgrade_subcommand, &definition.upgrade_subcommand);
+        replace(&mut self.autoconfirm, &definition.autoconfirm);
+        if let Some(needs_root) = definition.needs_root {
+            self.needs_root = needs_root;
         }
+        self
+    }
+
+    /// Set the command used to check whether a package is installed
+    fn with_query(mut self, command: &str, args: &[&str], installed_marker: &str) -> Self {
+        self.query_command = command.to_string();
+        self.query_args = args.iter().map(|a| a.to_string()).collect();
+        self.installed_marker = installed_marker.to_string();
+        self
     }
 
     /// Get the system update operation for this package manager.
@@ -121,17 +236,23 @@ impl PackageManager {
         if self.update_subcommand == self.upgrade_subcommand {
             None
         } else {
-            Some(SystemRefreshOperation::new(
-                &self.name,
-                &self.update_subcommand,
-                &self.autoconfirm,
-            ))
+            Some(SystemRefreshOperation {
+                needs_root: self.needs_root,
+                ..SystemRefreshOperation::new(
+                    &self.name,
+                    &self.update_subcommand,
+                    &self.autoconfirm,
+                )
+            })
         }
     }
 
     /// Get the system upgrade operation for this package manager
     pub fn upgrade_operation(&self) -> impl RunnableOperation {
-        SystemRefreshOperation::new(&self.name, &self.upgrade_subcommand, &self.autoconfirm)
+        SystemRefreshOperation {
+            needs_root: self.needs_root,
+            ..SystemRefreshOperation::new(&self.name, &self.upgrade_subcommand, &self.autoconfirm)
+        }
     }
 
     /// This is the name of the package manager (e.g. `apt-get`, `pacman`)
@@ -165,22 +286,95 @@ impl PackageManager {
     pub fn can_run(&self) -> bool {
         self.name().is_some()
     }
+
+    /// Whether this package manager knows how to upgrade the system
+    pub fn can_upgrade(&self) -> bool {
+        self.can_run() && !self.upgrade_subcommand.is_empty()
+    }
+
+    /// Whether installing packages needs to be run via `sudo`
+    pub fn needs_root(&self) -> bool {
+        self.needs_root
+    }
+
+    /// Get the operation that checks whether `package` is installed, or `None` if
+    /// this package manager has no way to query its packages
+    pub fn query_operation(&self, package: &str) -> Option<impl RunnableOperation> {
+        if self.query_command.is_empty() {
+            return None;
+        }
+
+        let mut args = self.query_args.clone();
+        args.push(package.to_string());
+        Some(PackageQueryOperation {
+            command_name: self.query_command.clone(),
+            args,
+        })
+    }
+
+    /// Whether `output` from a [`query_operation`](#method.query_operation) shows
+    /// that the package is installed
+    pub fn query_shows_installed(&self, output: &CommandOutput) -> bool {
+        output.code == Some(0)
+            && (self.installed_marker.is_empty()
+                || String::from_utf8_lossy(&output.stdout).contains(&self.installed_marker))
+    }
 }
 
 impl From<TargetOperatingSystem> for PackageManager {
     fn from(target_os: TargetOperatingSystem) -> Self {
         match target_os {
             TargetOperatingSystem::Arch => {
-                PackageManager::new("pacman", "-S", "-Sy", "-Syu", "--noconfirm")
+                PackageManager::new("pacman", "-S", "-Sy", "-Syu", "--noconfirm").with_query(
+                    "pacman",
+                    &["-Q"],
+                    "",
+                )
             }
             TargetOperatingSystem::Debian => {
-                PackageManager::new("apt-get", "install", "update", "upgrade", "-y")
+                PackageManager::new("apt-get", "install", "update", "upgrade", "-y").with_query(
+                    "dpkg-query",
+                    &["-W", "-f=${Status}"],
+                    "install ok installed",
+                )
             }
             TargetOperatingSystem::Fedora => {
                 PackageManager::new("dnf", "install", "upgrade", "upgrade", "--assumeyes")
+                    .with_query("rpm", &["-q"], "")
             }
             TargetOperatingSystem::RedHat => {
                 PackageManager::new("yum", "install", "upgrade", "upgrade", "--assumeyes")
+                    .with_query("rpm", &["-q"], "")
+            }
+            TargetOperatingSystem::OpenSuse => {
+                // zypper refreshes its repositories as part of an update
+                PackageManager::new("zypper", "install", "update", "update", "-y").with_query(
+                    "rpm",
+                    &["-q"],
+                    "",
+                )
+            }
+            TargetOperatingSystem::Alpine => PackageManager::new(
+                "apk", "add", "update", "upgrade", "",
+            )
+            .with_query("apk", &["info", "-e"], ""),
+            TargetOperatingSystem::Void => {
+                PackageManager::new("xbps-install", "-S", "-S", "-Su", "-y").with_query(
+                    "xbps-query",
+                    &[],
+                    "",
+                )
+            }
+            TargetOperatingSystem::Gentoo => {
+                // emerge only asks for confirmation when given `--ask`, and `--noreplace`
+                // already leaves installed packages alone
+                PackageManager::new(
+                    "emerge",
+                    "--noreplace",
+                    "--sync",
+                    "--update --deep --newuse @world",
+                    "",
+                )
             }
             TargetOperatingSystem::Unknown => PackageManager::new("", "", "", "", ""),
         }
@@ -194,30 +388,86 @@ pub enum TargetOperatingSystem {
     Debian,
     RedHat, // RedHat is distinct until dnf is shipped by default
     Fedora,
+    OpenSuse,
+    Alpine,
+    Void,
+    Gentoo,
     Unknown,
 }
 
 /// Collection of details for the current host system
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct SystemDetails {
     target_os: TargetOperatingSystem,
+    os_version: Option<String>,
+    arch: String,
+    hostname: Option<String>,
+    custom_package_manager: Option<Arc<PackageManagerDefinition>>,
 }
 
 impl SystemDetails {
     /// Create an instance of [`SystemDetails`](struct.SystemDetails.html) using the provided target os
     pub fn new(target_os: TargetOperatingSystem) -> Self {
-        SystemDetails { target_os }
+        SystemDetails {
+            target_os,
+            os_version: None,
+            arch: String::from(env::consts::ARCH),
+            hostname: None,
+            custom_package_manager: None,
+        }
+    }
+
+    /// Set the version of the OS, the `VERSION_ID` from `/etc/os-release`
+    pub fn with_os_version(mut self, os_version: String) -> Self {
+        self.os_version = Some(os_version);
+        self
+    }
+
+    /// Set the hostname of this system
+    pub fn with_hostname(mut self, hostname: String) -> Self {
+        self.hostname = Some(hostname);
+        self
+    }
+
+    /// Use the package manager declared in `definition` on top of the built-in one for this system
+    pub fn with_package_manager(mut self, definition: PackageManagerDefinition) -> Self {
+        self.custom_package_manager = Some(Arc::new(definition));
+        self
     }
 
     /// Get the [`PackageManager`](struct.PackageManager.html) that corresponds with this system
-    pub fn package_manager(self) -> PackageManager {
-        PackageManager::from(self.target_os)
+    pub fn package_manager(&self) -> PackageManager {
+        let built_in = PackageManager::from(self.target_os);
+        match &self.custom_package_manager {
+            Some(definition) => built_in.with_definition(definition),
+            None => built_in,
+        }
     }
 
     /// Gets the current OS that is being run on
-    pub fn current_os(self) -> TargetOperatingSystem {
+    pub fn current_os(&self) -> TargetOperatingSystem {
         self.target_os
     }
+
+    /// The version of the current OS, if it is known
+    pub fn os_version(&self) -> Option<&str> {
+        self.os_version.as_deref()
+    }
+
+    /// The CPU architecture, such as `x86_64`
+    pub fn arch(&self) -> &str {
+        &self.arch
+    }
+
+    /// The kind of OS, such as `linux`, as it is usually named in release downloads
+    pub fn os(&self) -> &str {
+        env::consts::OS
+    }
+
+    /// The hostname of this system, if it is known
+    pub fn hostname(&self) -> Option<&str> {
+        self.hostname.as_deref()
+    }
 }
 
 impl From<sys_info::LinuxOSReleaseInfo> for SystemDetails {
@@ -235,7 +485,9 @@ impl From<sys_info::LinuxOSReleaseInfo> for SystemDetails {
                 }
             }
         }
-        SystemDetails::new(current_id)
+        let mut details = SystemDetails::new(current_id);
+        details.os_version = info.version_id;
+        details
     }
 }
 
@@ -250,9 +502,13 @@ impl From<&str> for TargetOperatingSystem {
 
 impl Default for SystemDetails {
     fn default() -> Self {
-        match sys_info::linux_os_release() {
+        let details = match sys_info::linux_os_release() {
             Ok(release) => SystemDetails::from(release),
             Err(_) => SystemDetails::new(TargetOperatingSystem::Unknown),
+        };
+        SystemDetails {
+            hostname: sys_info::hostname().ok(),
+            ..details
         }
     }
 }
@@ -398,6 +654,42 @@ mod tests {
             Some(String::from("unknowndistro")),
             Some(String::from("rhel")),
             TargetOperatingSystem::RedHat
+        );
+        (
+            opensuse_tumbleweed,
+            Some(String::from("opensuse-tumbleweed")),
+            Some(String::from("opensuse suse")),
+            TargetOperatingSystem::OpenSuse
+        );
+        (
+            opensuse_leap,
+            Some(String::from("opensuse-leap")),
+            Some(String::from("suse opensuse")),
+            TargetOperatingSystem::OpenSuse
+        );
+        (
+            opensuse_from_id_like,
+            Some(String::from("unknowndistro")),
+            Some(String::from("suse")),
+            TargetOperatingSystem::OpenSuse
+        );
+        (
+            alpine,
+            Some(String::from("alpine")),
+            None,
+            TargetOperatingSystem::Alpine
+        );
+        (
+            void,
+            Some(String::from("void")),
+            None,
+            TargetOperatingSystem::Void
+        );
+        (
+            gentoo,
+            Some(String::from("gentoo")),
+            None,
+            TargetOperatingSystem::Gentoo
         )
     );
 
@@ -426,7 +718,7 @@ mod tests {
                 "-Sy",
                 "-Syu",
                 "--noconfirm",
-            ),
+            ).with_query("pacman", &["-Q"], ""),
             true
         );
         (
@@ -438,7 +730,7 @@ mod tests {
                 "update",
                 "upgrade",
                 "-y"
-            ),
+            ).with_query("dpkg-query", &["-W", "-f=${Status}"], "install ok installed"),
             true
         );
         (
@@ -456,13 +748,48 @@ mod tests {
         (
             fedora,
             TargetOperatingSystem::Fedora,
-            PackageManager::new("dnf", "install", "upgrade", "upgrade", "--assumeyes"),
+            PackageManager::new("dnf", "install", "upgrade", "upgrade", "--assumeyes")
+                .with_query("rpm", &["-q"], ""),
             true
         );
         (
             redhat,
             TargetOperatingSystem::RedHat,
-            PackageManager::new("yum", "install", "upgrade", "upgrade", "--assumeyes"),
+            PackageManager::new("yum", "install", "upgrade", "upgrade", "--assumeyes")
+                .with_query("rpm", &["-q"], ""),
+            true
+        );
+        (
+            opensuse,
+            TargetOperatingSystem::OpenSuse,
+            PackageManager::new("zypper", "install", "update", "update", "-y")
+                .with_query("rpm", &["-q"], ""),
+            true
+        );
+        (
+            alpine,
+            TargetOperatingSystem::Alpine,
+            PackageManager::new("apk", "add", "update", "upgrade", "")
+                .with_query("apk", &["info", "-e"], ""),
+            true
+        );
+        (
+            void,
+            TargetOperatingSystem::Void,
+            PackageManager::new("xbps-install", "-S", "-S", "-Su", "-y")
+                .with_query("xbps-query", &[], ""),
+            true
+        );
+        (
+            gentoo,
+            TargetOperatingSystem::Gentoo,
+            PackageManager::new(
+                "emerge",
+                "--noreplace",
+                "--sync",
+                "--update --deep --newuse @world",
+                ""
+            ),
             true
         )
     );
@@ -474,6 +801,18 @@ mod tests {
         assert_eq!(expected, actual.target_os);
     }
 
+    #[test]
+    fn test_release_info_sets_version() {
+        let info = sys_info::LinuxOSReleaseInfo {
+            id: Some(String::from("ubuntu")),
+            version_id: Some(String::from("20.04")),
+            ..sys_info::LinuxOSReleaseInfo::default()
+        };
+        let sd = SystemDetails::from(info);
+        assert_eq!(sd.os_version(), Some("20.04"));
+        assert_eq!(sd.arch(), env::consts::ARCH);
+    }
+
     #[test]
     fn test_current_os_reflects_target() {
         let expected = TargetOperatingSystem::Debian;
@@ -493,10 +832,113 @@ mod tests {
         assert!(actual.update_operation().is_none());
     }
 
+    #[test]
+    fn test_no_update_opensuse() {
+        let actual = PackageManager::from(TargetOperatingSystem::OpenSuse);
+        assert!(actual.update_operation().is_none());
+    }
+
+    #[test]
+    fn test_refresh_args_skip_empty_autoconfirm() {
+        let actual = PackageManager::from(TargetOperatingSystem::Alpine).upgrade_operation();
+        let args = actual.args(&SystemDetails::new(TargetOperatingSystem::Alpine));
+        assert_eq!(args.unwrap(), vec!["upgrade"]);
+    }
+
+    #[test]
+    fn test_refresh_args_split_subcommand() {
+        let actual = PackageManager::from(TargetOperatingSystem::Gentoo).upgrade_operation();
+        let args = actual.args(&SystemDetails::new(TargetOperatingSystem::Gentoo));
+        assert_eq!(
+            args.unwrap(),
+            vec!["--update", "--deep", "--newuse", "@world"]
+        );
+    }
+
+    #[test]
+    fn test_custom_package_manager_overrides_fields() {
+        let sd = SystemDetails::new(TargetOperatingSystem::Arch).with_package_manager(
+            PackageManagerDefinition {
+                binary: Some(String::from("paru")),
+                needs_root: Some(false),
+                ..PackageManagerDefinition::default()
+            },
+        );
+        let actual = sd.package_manager();
+        let mut expected = PackageManager::new("paru", "-S", "-Sy", "-Syu", "--noconfirm")
+            .with_query("pacman", &["-Q"], "");
+        expected.needs_root = false;
+        assert_eq!(expected, actual);
+
+        let upgrade = actual.upgrade_operation();
+        assert_eq!(upgrade.command_name(&sd).unwrap(), "paru");
+        assert!(!upgrade.needs_root(&sd));
+    }
+
+    #[test]
+    fn test_custom_package_manager_on_unknown_system() {
+        let sd = SystemDetails::new(TargetOperatingSystem::Unknown).with_package_manager(
+            PackageManagerDefinition {
+                binary: Some(String::from("pkg")),
+                install_subcommand: Some(String::from("install")),
+                autoconfirm: Some(String::from("-y")),
+                ..PackageManagerDefinition::default()
+            },
+        );
+        let actual = sd.package_manager();
+        assert!(actual.can_run());
+        assert!(actual.needs_root());
+        assert_eq!(actual.install_subcommand(), Some(String::from("install")));
+        // No upgrade subcommand was given, so upgrading can't work
+        assert!(!actual.can_upgrade());
+    }
+
+    #[test]
+    fn test_no_query_unknown() {
+        let actual = PackageManager::from(TargetOperatingSystem::Unknown);
+        assert!(actual.query_operation("git").is_none());
+    }
+
+    #[test]
+    fn test_query_operation_appends_package() {
+        let actual = PackageManager::from(TargetOperatingSystem::Debian)
+            .query_operation("git")
+            .unwrap();
+        let sd = SystemDetails::new(TargetOperatingSystem::Debian);
+        assert_eq!(actual.command_name(&sd).unwrap(), "dpkg-query");
+        assert_eq!(actual.args(&sd).unwrap(), vec!["-W", "-f=${Status}", "git"]);
+        assert!(!actual.needs_root(&SystemDetails::default()));
+    }
+
+    #[test]
+    fn test_query_shows_installed_exit_code() {
+        let manager = PackageManager::from(TargetOperatingSystem::Arch);
+        assert!(manager.query_shows_installed(&CommandOutput::success()));
+        assert!(!manager.query_shows_installed(&CommandOutput {
+            code: Some(1),
+            ..CommandOutput::default()
+        }));
+    }
+
+    #[test]
+    fn test_query_shows_installed_marker() {
+        let manager = PackageManager::from(TargetOperatingSystem::Debian);
+        assert!(manager.query_shows_installed(&CommandOutput {
+            code: Some(0),
+            stdout: b"install ok installed".to_vec(),
+            stderr: Vec::new(),
+        }));
+        assert!(!manager.query_shows_installed(&CommandOutput {
+            code: Some(0),
+            stdout: b"deinstall ok config-files".to_vec(),
+            stderr: Vec::new(),
+        }));
+    }
+
     #[test]
     fn test_system_refresh_runable_command_name_value() {
         let actual = SystemRefreshOperation::new("apt", "", "");
-        let command = actual.command_name(SystemDetails::new(TargetOperatingSystem::Debian));
+        let command = actual.command_name(&SystemDetails::new(TargetOperatingSystem::Debian));
         assert!(command.is_ok());
         assert_eq!(command.unwrap(), "apt");
     }
@@ -504,7 +946,7 @@ mod tests {
     #[test]
     fn test_system_refresh_runnable_err_command_name() {
         let actual = SystemRefreshOperation::new("", "", "");
-        let command = actual.command_name(SystemDetails::new(TargetOperatingSystem::Debian));
+        let command = actual.command_name(&SystemDetails::new(TargetOperatingSystem::Debian));
         assert!(command.is_err());
     }
 }
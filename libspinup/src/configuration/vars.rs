@@ -0,0 +1,266 @@
+//! The `vars` module replaces `${...}` references in configuration values
+//! once the configuration has been read in.
+//!
+//! The following references are supported:
+//!
+//! - `${name}`: A variable from the `vars` section, or one given on the command line
+//! - `${HOME}`: The current user's home directory, unless `vars` defines `HOME`
+//! - `${env:NAME}`: The `NAME` environment variable
+//!
+//! A literal `${` can be written as `$${`.
+
+use std::collections::BTreeMap;
+use std::env;
+
+use crate::error::{Error, Result};
+
+use super::{Configuration, CustomCommand};
+
+/// Replace every variable reference in the values of `config` that support them.
+///
+/// `overrides` take precedence over the `vars` section of the configuration.
+pub(super) fn substitute_vars(
+    config: &mut Configuration,
+    overrides: &BTreeMap<String, String>,
+) -> Result<()> {
+    let mut vars = config.vars.clone().unwrap_or_default();
+    vars.extend(overrides.clone());
+    let vars = Variables { vars };
+
+    if let Some(commands) = &mut config.custom_commands {
+        for (i, command) in commands.iter_mut().enumerate() {
+            vars.expand_command(command, &format!("custom_commands[{}]", i))?;
+        }
+    }
+
+    if let Some(sets) = &mut config.command_sets {
+        for set in sets.iter_mut() {
+            for (i, ordered) in set.commands.iter_mut().enumerate() {
+                let location = format!("command set {}, commands[{}]", set.name, i);
+                vars.expand_command(&mut ordered.command, &location)?;
+            }
+        }
+    }
+
+    if let Some(operations) = &mut config.file_downloads {
+        for (i, operation) in operations.iter_mut().enumerate() {
+            let location = format!("file_downloads[{}]", i);
+            if let Some(base_dir) = &mut operation.base_dir {
+                vars.expand_in_place(base_dir, &format!("{}.base_dir", location))?;
+            }
+            for (j, file) in operation.files.iter_mut().enumerate() {
+                let file_location = format!("{}.files[{}]", location, j);
+                vars.expand_in_place(&mut file.source, &format!("{}.source", file_location))?;
+                vars.expand_in_place(&mut file.target, &format!("{}.target", file_location))?;
+                if let Some(release) = &mut file.github_release {
+                    let release_location = format!("{}.github_release", file_location);
+                    vars.expand_in_place(&mut release.repo, &format!("{}.repo", release_location))?;
+                    vars.expand_in_place(&mut release.tag, &format!("{}.tag", release_location))?;
+                    vars.expand_in_place(
+                        &mut release.asset,
+                        &format!("{}.asset", release_location),
+                    )?;
+                }
+            }
+            if let Some(after) = &mut operation.after_complete {
+                vars.expand_command(after, &format!("{}.after_complete", location))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The variables that can be referenced
+struct Variables {
+    vars: BTreeMap<String, String>,
+}
+
+impl Variables {
+    fn expand_command(&self, command: &mut CustomCommand, location: &str) -> Result<()> {
+        if let Some(args) = &mut command.args {
+            for arg in args.iter_mut() {
+                self.expand_in_place(arg, &format!("{}.args", location))?;
+            }
+        }
+        if let Some(cwd) = &mut command.cwd {
+            self.expand_in_place(cwd, &format!("{}.cwd", location))?;
+        }
+        if let Some(env) = &mut command.env {
+            for (name, value) in env.iter_mut() {
+                self.expand_in_place(value, &format!("{}.env.{}", location, name))?;
+            }
+        }
+        let guards = &mut command.guards;
+        for (name, value) in [
+            ("creates", &mut guards.creates),
+            ("removes", &mut guards.removes),
+            ("unless", &mut guards.unless),
+            ("only_if", &mut guards.only_if),
+        ] {
+            if let Some(value) = value {
+                self.expand_in_place(value, &format!("{}.{}", location, name))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn expand_in_place(&self, value: &mut String, location: &str) -> Result<()> {
+        *value = self
+            .expand(value)
+            .map_err(|e| Error::from(format!("{} in {}", e, location)))?;
+        Ok(())
+    }
+
+    /// Replace every reference in `value`
+    fn expand(&self, value: &str) -> Result<String> {
+        let mut expanded = String::with_capacity(value.len());
+        let mut rest = value;
+
+        while let Some(start) = rest.find("${") {
+            if rest[..start].ends_with('$') {
+                // `$${` is an escaped `${`
+                expanded.push_str(&rest[..start]);
+                expanded.push('{');
+                rest = &rest[start + 2..];
+                continue;
+            }
+
+            expanded.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| Error::from(format!("Unterminated variable in `{}`", value)))?;
+            let name = &rest[start + 2..start + end];
+            expanded.push_str(&self.lookup(name)?);
+            rest = &rest[start + end + 1..];
+        }
+
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+
+    fn lookup(&self, name: &str) -> Result<String> {
+        let undefined = || Error::from(format!("Undefined variable `${{{}}}`", name));
+
+        if let Some(env_name) = name.strip_prefix("env:") {
+            return env::var(env_name).map_err(|_| undefined());
+        }
+
+        if let Some(value) = self.vars.get(name) {
+            return Ok(value.clone());
+        }
+
+        match name {
+            "HOME" => dirs::home_dir()
+                .map(|home| home.to_string_lossy().into_owned())
+                .ok_or_else(undefined),
+            _ => Err(undefined()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables(pairs: &[(&str, &str)]) -> Variables {
+        Variables {
+            vars: pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_expand_user_vars() {
+        let vars = variables(&[("font", "FiraCode"), ("ver", "2")]);
+        assert_eq!(
+            vars.expand("${font}-v${ver}.zip").unwrap(),
+            "FiraCode-v2.zip"
+        );
+    }
+
+    #[test]
+    fn test_expand_env_and_home() {
+        env::set_var("SPINUP_VARS_TEST", "value");
+        let vars = variables(&[]);
+        assert_eq!(vars.expand("${env:SPINUP_VARS_TEST}").unwrap(), "value");
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(
+            vars.expand("${HOME}/.fonts").unwrap(),
+            format!("{}/.fonts", home.display())
+        );
+    }
+
+    #[test]
+    fn test_user_var_overrides_home() {
+        let vars = variables(&[("HOME", "/home/other")]);
+        assert_eq!(vars.expand("${HOME}").unwrap(), "/home/other");
+    }
+
+    #[test]
+    fn test_expand_leaves_other_text_alone() {
+        let vars = variables(&[]);
+        assert_eq!(
+            vars.expand("$PATH and $$ and $").unwrap(),
+            "$PATH and $$ and $"
+        );
+        assert_eq!(vars.expand("$${literal}").unwrap(), "${literal}");
+    }
+
+    #[test]
+    fn test_undefined_and_unterminated_are_errors() {
+        let vars = variables(&[]);
+        let err = vars.expand("${missing}").unwrap_err();
+        assert_eq!(err.to_string(), "Undefined variable `${missing}`");
+        assert!(vars.expand("${env:SPINUP_SURELY_UNSET_VAR}").is_err());
+        assert!(vars.expand("${open").is_err());
+    }
+
+    #[test]
+    fn test_substitute_vars_in_config() {
+        let mut config: Configuration = serde_json::from_str(
+            r#"{
+                "vars": { "dir": "/opt/fonts", "name": "fira" },
+                "custom_commands": [{ "command": "echo", "args": ["${name}"] }],
+                "file_downloads": [{
+                    "base_dir": "${dir}",
+                    "files": [{ "source": "https://example.com/${name}.zip", "target": "${name}.zip" }],
+                    "after_complete": { "command": "unzip", "args": ["${dir}/${name}.zip"] }
+                }]
+            }"#,
+        )
+        .unwrap();
+        let mut overrides = BTreeMap::new();
+        overrides.insert(String::from("name"), String::from("hack"));
+
+        substitute_vars(&mut config, &overrides).unwrap();
+        assert_eq!(
+            config.custom_commands.unwrap()[0].args,
+            Some(vec![String::from("hack")])
+        );
+        let download = &config.file_downloads.unwrap()[0];
+        assert_eq!(download.base_dir, Some(String::from("/opt/fonts")));
+        assert_eq!(download.files[0].source, "https://example.com/hack.zip");
+        assert_eq!(download.files[0].target, "hack.zip");
+        assert_eq!(
+            download.after_complete.as_ref().unwrap().args,
+            Some(vec![String::from("/opt/fonts/hack.zip")])
+        );
+    }
+
+    #[test]
+    fn test_substitute_vars_error_names_the_location() {
+        let mut config: Configuration = serde_json::from_str(
+            r#"{ "custom_commands": [{ "command": "ls" }, { "command": "echo", "args": ["${nope}"] }] }"#,
+        )
+        .unwrap();
+
+        let err = substitute_vars(&mut config, &BTreeMap::new()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Undefined variable `${nope}` in custom_commands[1].args"
+        );
+    }
+}
@@ -1,7 +1,9 @@
 use std::error;
 use std::fmt;
 use std::io;
+use std::path::PathBuf;
 use std::result;
+use std::time::Duration;
 
 use flexi_logger;
 use reqwest;
@@ -26,6 +28,18 @@ pub enum Error {
     Io(io::Error),
     Request(reqwest::Error),
     Logger(flexi_logger::FlexiLoggerError),
+    Multiple(Vec<Error>),
+    Stopped(Vec<Error>),
+    Checksum {
+        file: PathBuf,
+        algorithm: &'static str,
+        expected: String,
+        actual: String,
+    },
+    Timeout {
+        command: String,
+        timeout: Duration,
+    },
     Other(String),
 }
 
@@ -45,8 +59,51 @@ impl fmt::Display for Error {
             Error::Config(ref err) => err.fmt(f),
             Error::SystemDetails(ref err) => err.fmt(f),
             Error::Io(ref err) => err.fmt(f),
-            Error::Request(ref err) => err.fmt(f),
+            Error::Request(ref err) => match err.url().and_then(|url| url.password()) {
+                // Keep credentials given in the url out of the logs
+                Some(password) => write!(f, "{}", err.to_string().replace(password, "***")),
+                None => err.fmt(f),
+            },
             Error::Logger(ref err) => err.fmt(f),
+            Error::Multiple(ref errs) => {
+                write!(f, "{} operation(s) failed:", errs.len())?;
+                for err in errs {
+                    write!(f, "\n  - {}", err)?;
+                }
+                Ok(())
+            }
+            Error::Stopped(ref errs) => {
+                write!(
+                    f,
+                    "{} operation(s) failed, the last one stopped the run:",
+                    errs.len()
+                )?;
+                for err in errs {
+                    write!(f, "\n  - {}", err)?;
+                }
+                Ok(())
+            }
+            Error::Checksum {
+                ref file,
+                algorithm,
+                ref expected,
+                ref actual,
+            } => write!(
+                f,
+                "{} mismatch for {}: expected {}, got {}",
+                algorithm,
+                file.display(),
+                expected,
+                actual
+            ),
+            Error::Timeout {
+                ref command,
+                timeout,
+            } => write!(
+                f,
+                "`{}` did not finish within {:?} and was stopped",
+                command, timeout
+            ),
             Error::Other(ref s) => write!(f, "{}", s),
         }
     }
@@ -146,3 +203,17 @@ impl From<&str> for Error {
         Error::Other(String::from(msg))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multiple_display_lists_every_error() {
+        let err = Error::Multiple(vec![Error::from("first"), Error::from("second")]);
+        assert_eq!(
+            err.to_string(),
+            "2 operation(s) failed:\n  - first\n  - second"
+        );
+    }
+}
@@ -9,6 +9,8 @@ extern crate log;
 #[macro_use]
 extern crate lazy_static;
 
+use std::sync::Arc;
+
 use flexi_logger::Logger;
 
 pub mod configuration;
@@ -16,52 +18,94 @@ pub mod error;
 pub mod operations;
 pub mod runconfig;
 
-use configuration::read_in_config;
+use configuration::{read_in_config_with_vars, Configuration};
 use error::{Error, Result};
 use operations::{
-    execute_download_operations, install_packages, install_snap_packages, process_is_root,
-    run_custom_commands,
+    build_plan, execute_download_operations, install_packages, install_snap_packages,
+    process_is_root, run_custom_commands, CommandRunner, Failures, ProcessRunner, RunReport,
 };
 
-pub use runconfig::RunConfig;
+pub use runconfig::{OutputMode, PlanFormat, RunConfig};
 
+/// Run the app, spawning every command as a child process
 pub async fn run_app(run_config: RunConfig) -> Result<()> {
+    let runner = ProcessRunner::new(run_config.output);
+    run_app_with_runner(run_config, Arc::new(runner)).await
+}
+
+/// Run the app, executing every command through the given [`CommandRunner`](operations/trait.CommandRunner.html)
+pub async fn run_app_with_runner(
+    run_config: RunConfig,
+    runner: Arc<dyn CommandRunner>,
+) -> Result<()> {
     Logger::with_str(run_config.log_level).start().unwrap();
 
     if process_is_root() {
         return Err(Error::from("spinup should not be run as root"));
     }
 
-    let config = read_in_config(&run_config.config_file_path)?;
+    let mut config = read_in_config_with_vars(&run_config.config_file_path, &run_config.vars)?;
+
+    if run_config.keep_going {
+        config.continue_on_error = true;
+    }
 
     if run_config.print_parsed {
         println!("{:#?}", config);
     }
 
+    if let Some(format) = run_config.plan {
+        let plan = build_plan(&config, &run_config)?;
+        match format {
+            PlanFormat::Text => print!("{}", plan),
+            PlanFormat::Json => println!("{}", plan.to_json()?),
+        }
+        return Ok(());
+    }
+
+    let report = Arc::new(RunReport::new());
+    let result = run_phases(&config, &run_config, &runner, &report).await;
+
+    if let Some(report_path) = &run_config.report_path {
+        if let Err(e) = report.write_to(report_path, &result) {
+            error!("Unable to write the run report to {}: {}", report_path, e);
+        }
+    }
+
+    result
+}
+
+async fn run_phases(
+    config: &Configuration,
+    run_config: &RunConfig,
+    runner: &Arc<dyn CommandRunner>,
+    report: &Arc<RunReport>,
+) -> Result<()> {
+    let mut failures = Failures::default();
+
     if run_config.run_package_installs {
         debug!("Installing packages");
-        install_packages(&config)?;
+        failures.gather(install_packages(config, &**runner, report))?;
     }
 
     if run_config.run_file_downloads {
         debug!("Downloading files");
-        let dls = execute_download_operations(&config).await;
-        if let Err(e) = dls {
-            return Err(e);
-        }
+        failures.gather(
+            execute_download_operations(config, run_config.force_downloads, runner, report).await,
+        )?;
     }
 
     if run_config.run_custom_commands {
         debug!("Running custom commands");
-        run_custom_commands(&config)?;
+        failures.gather(run_custom_commands(config, &**runner, report))?;
     }
 
     if run_config.run_snap_installs {
         debug!("Installing snaps");
-        install_snap_packages(&config)?;
+        failures.gather(install_snap_packages(config, &**runner, report))?;
     }
 
-    Ok(())
+    failures.into_result()
 }
 
 #[cfg_attr(tarpaulin, skip)]
@@ -95,3 +139,48 @@ pub fn generate_configurations(source_format: &str) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use operations::RecordingRunner;
+
+    #[tokio::test]
+    async fn test_failure_that_is_not_allowed_stops_later_phases() {
+        let config: Configuration = serde_json::from_str(
+            r#"{
+                "custom_commands": [
+                    { "command": "allowed", "continue_on_error": true },
+                    { "command": "fatal" },
+                    { "command": "never" }
+                ],
+                "snaps": { "standard_snaps": { "names": ["spotify"] } }
+            }"#,
+        )
+        .unwrap();
+        let run_config = RunConfig::new(String::new(), "off", true, true, true, true, false);
+        let runner = Arc::new(RecordingRunner::new());
+        runner.fail_command("allowed");
+        runner.fail_command("fatal");
+
+        let shared: Arc<dyn CommandRunner> = runner.clone();
+        let result = run_phases(&config, &run_config, &shared, &Arc::default()).await;
+        match result {
+            Err(Error::Stopped(errors)) => assert_eq!(errors.len(), 2),
+            other => panic!("Unexpected result {:?}", other),
+        }
+        let run: Vec<String> = runner
+            .commands()
+            .iter()
+            .map(|c| c.command.clone())
+            .collect();
+        assert_eq!(run, vec!["allowed", "fatal"]);
+    }
+
+    #[test]
+    fn test_run_app_can_be_spawned() {
+        fn assert_send<T: Send>(_: &T) {}
+        let run_config = RunConfig::new(String::new(), "off", true, true, true, true, false);
+        assert_send(&run_app(run_config));
+    }
+}
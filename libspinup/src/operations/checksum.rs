@@ -0,0 +1,185 @@
+//! The checksum module verifies downloaded files against the digests
+//! given in their [`FileDownloadDefinition`](../../configuration/struct.FileDownloadDefinition.html).
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::configuration::FileDownloadDefinition;
+use crate::error::{Error, Result};
+
+/// Hashes a download as it arrives, for each digest its definition expects.
+///
+/// The SHA-256 digest and size are always worked out, as they are kept in the download cache.
+pub(crate) struct Verifier {
+    sha256: Sha256,
+    expected_sha256: Option<String>,
+    sha512: Option<(Sha512, String)>,
+    size: u64,
+}
+
+/// The details of a download that passed verification
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Verified {
+    pub sha256: String,
+    pub size: u64,
+}
+
+impl Verifier {
+    /// Create a verifier for the digests set on `definition`
+    pub(crate) fn new(definition: &FileDownloadDefinition) -> Self {
+        Verifier {
+            sha256: Sha256::new(),
+            expected_sha256: definition.sha256.as_deref().map(normalize),
+            sha512: definition
+                .sha512
+                .as_ref()
+                .map(|expected| (Sha512::new(), normalize(expected))),
+            size: 0,
+        }
+    }
+
+    /// Add the next part of the download to each digest
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+        if let Some((hasher, _)) = &mut self.sha512 {
+            hasher.update(data);
+        }
+        self.size += data.len() as u64;
+    }
+
+    /// Check every digest against what was expected, naming `file_path` in the error on a mismatch
+    pub(crate) fn verify(self, file_path: &Path) -> Result<Verified> {
+        let sha256 = hex::encode(self.sha256.finalize());
+        if let Some(expected) = self.expected_sha256 {
+            check(file_path, "sha256", expected, sha256.clone())?;
+        }
+        if let Some((hasher, expected)) = self.sha512 {
+            check(
+                file_path,
+                "sha512",
+                expected,
+                hex::encode(hasher.finalize()),
+            )?;
+        }
+        Ok(Verified {
+            sha256,
+            size: self.size,
+        })
+    }
+}
+
+/// Verify the file already at `path` against the digests set on `definition`
+pub(crate) fn verify_file(definition: &FileDownloadDefinition, path: &Path) -> Result<Verified> {
+    let mut file = File::open(path)?;
+    let mut verifier = Verifier::new(definition);
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        verifier.update(&buffer[..read]);
+    }
+    verifier.verify(path)
+}
+
+fn normalize(digest: &str) -> String {
+    digest.trim().to_lowercase()
+}
+
+fn check(
+    file_path: &Path,
+    algorithm: &'static str,
+    expected: String,
+    actual: String,
+) -> Result<()> {
+    if expected == actual {
+        debug!("{} {} matches", file_path.display(), algorithm);
+        Ok(())
+    } else {
+        Err(Error::Checksum {
+            file: file_path.to_path_buf(),
+            algorithm,
+            expected,
+            actual,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    const HELLO_SHA512: &str = "9b71d224bd62f3785d96d46ad3ea3d73319bfbc2890caadae2dff72519673ca72323c3d99ba5c11d7c7acc6e14b8c5da0c4663475c2e5c3adef46f73bcdec043";
+
+    fn definition(sha256: Option<&str>, sha512: Option<&str>) -> FileDownloadDefinition {
+        FileDownloadDefinition {
+            source: String::from("https://example.com/hello"),
+            github_release: None,
+            target: String::from("hello"),
+            sha256: sha256.map(String::from),
+            sha512: sha512.map(String::from),
+            extract: None,
+            mode: None,
+            owner: None,
+            group: None,
+            headers: None,
+            auth: None,
+            config_dir: None,
+        }
+    }
+
+    fn verify(definition: &FileDownloadDefinition, parts: &[&[u8]]) -> Result<Verified> {
+        let mut verifier = Verifier::new(definition);
+        for part in parts {
+            verifier.update(part);
+        }
+        verifier.verify(Path::new("/tmp/hello"))
+    }
+
+    #[test]
+    fn test_matching_digests_pass() {
+        let upper = HELLO_SHA256.to_uppercase();
+        let both = definition(Some(&upper), Some(HELLO_SHA512));
+        assert!(verify(&both, &[b"he", b"llo"]).is_ok());
+        let unchecked = verify(&definition(None, None), &[b"hel", b"lo"]).unwrap();
+        assert_eq!(unchecked.sha256, HELLO_SHA256);
+        assert_eq!(unchecked.size, 5);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hello");
+        std::fs::write(&path, b"hello").unwrap();
+        let verified = verify_file(&definition(None, Some(HELLO_SHA512)), &path).unwrap();
+        assert_eq!(verified.sha256, HELLO_SHA256);
+        assert!(verify_file(&definition(None, Some(HELLO_SHA256)), &path).is_err());
+    }
+
+    #[test]
+    fn test_mismatch_names_file_and_digests() {
+        let sha256_only = definition(Some(HELLO_SHA256), None);
+        match verify(&sha256_only, &[b"goodbye"]) {
+            Err(Error::Checksum {
+                file,
+                algorithm,
+                expected,
+                actual,
+            }) => {
+                assert_eq!(file, Path::new("/tmp/hello"));
+                assert_eq!(algorithm, "sha256");
+                assert_eq!(expected, HELLO_SHA256);
+                assert_ne!(actual, HELLO_SHA256);
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        let wrong_sha512 = definition(Some(HELLO_SHA256), Some(HELLO_SHA256));
+        let err = verify(&wrong_sha512, &[b"hello"]).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("sha512 mismatch for /tmp/hello"));
+    }
+}
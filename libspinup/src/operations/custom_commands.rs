@@ -1,32 +1,190 @@
-use crate::configuration::{CommandSet, Configuration};
+use crate::configuration::Configuration;
 use crate::error::Result;
 
-use super::run_command;
+use super::{
+    run_command, CommandRunner, Conditional, Failures, Phase, RunReport, RunnableOperation,
+};
 
 /// Run the custom commands and command sets that are in the configuration
-pub fn run_custom_commands(config: &Configuration) -> Result<()> {
+pub fn run_custom_commands(
+    config: &Configuration,
+    runner: &dyn CommandRunner,
+    report: &RunReport,
+) -> Result<()> {
+    let mut failures = Failures::default();
+    let result = visit_custom_command_operations(config, &mut |phase, op| {
+        let result = run_command(phase, op, &config.system_details, runner, report);
+        failures.allow(result, config.continue_on_error || op.continue_on_error())
+    });
+    failures.gather(result)?;
+    failures.into_result()
+}
+
+/// Walk the custom commands, followed by each command set, in the order they
+/// should be run, passing each one to `visit`.
+pub(crate) fn visit_custom_command_operations(
+    config: &Configuration,
+    visit: &mut dyn FnMut(Phase, &dyn RunnableOperation) -> Result<()>,
+) -> Result<()> {
     if let Some(commands) = &config.custom_commands {
-        commands
-            .iter()
-            .map(|c| run_command(c, config.system_details))
-            .collect::<Result<Vec<()>>>()
-            .map(|_| ())?;
+        for command in commands {
+            visit(Phase::CustomCommands, command)?;
+        }
     }
 
     if let Some(command_sets) = &config.command_sets {
         for command_set in command_sets {
-            run_collected_commands(command_set, config)?;
+            for command in command_set.get_runnable_commands() {
+                let command = Conditional::new(&command, command_set.when.as_ref());
+                visit(Phase::CommandSets, &command)?;
+            }
         }
     }
 
     Ok(())
 }
 
-fn run_collected_commands(command_set: &CommandSet, config: &Configuration) -> Result<()> {
-    command_set
-        .get_runnable_commands()
-        .iter()
-        .map(|c| run_command(c, config.system_details))
-        .collect::<Result<Vec<_>>>()
-        .map(|_| ())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::{
+        CommandSet, Condition, CustomCommand, OrderedCommand, SystemDetails, TargetOperatingSystem,
+    };
+    use crate::error::Error;
+    use crate::operations::RecordingRunner;
+
+    fn test_config(continue_on_error: bool) -> Configuration {
+        let mut config: Configuration = serde_json::from_str("{}").unwrap();
+        let mut failing = CustomCommand::new(String::from("false"), None, false);
+        failing.continue_on_error = continue_on_error;
+        config.custom_commands = Some(vec![
+            failing,
+            CustomCommand::new(String::from("true"), None, false),
+        ]);
+        config.command_sets = Some(vec![CommandSet {
+            name: String::from("set"),
+            commands: vec![OrderedCommand {
+                id: 0,
+                command: CustomCommand::new(String::from("false"), None, false),
+            }],
+            when: None,
+        }]);
+        config
+    }
+
+    #[test]
+    fn test_unmet_conditions_are_skipped() {
+        let mut config = test_config(false);
+        config.system_details = SystemDetails::new(TargetOperatingSystem::Arch);
+        let not_arch = Condition {
+            os: Some(String::from("debian")),
+            ..Condition::default()
+        };
+        config.custom_commands.as_mut().unwrap()[0].when = Some(not_arch.clone());
+        config.command_sets.as_mut().unwrap()[0].when = Some(not_arch);
+
+        let runner = RecordingRunner::new();
+        runner.fail_command("false");
+        let report = RunReport::new();
+        assert!(run_custom_commands(&config, &runner, &report).is_ok());
+        assert_eq!(runner.commands().len(), 1);
+        let entries = report.entries();
+        let skipped: Vec<_> = entries.iter().filter(|e| e.skipped).collect();
+        assert_eq!(skipped.len(), 2);
+        assert_eq!(skipped[0].operation, "false");
+        assert_eq!(
+            skipped[1].skip_reason,
+            Some(String::from("the os is not debian"))
+        );
+    }
+
+    #[test]
+    fn test_guards_skip_commands_in_sets() {
+        let mut config = test_config(false);
+        config.custom_commands.as_mut().unwrap()[1].guards.unless = Some(String::from("true"));
+        config.command_sets.as_mut().unwrap()[0].commands[0]
+            .command
+            .guards
+            .only_if = Some(String::from("false"));
+
+        let runner = RecordingRunner::new();
+        runner.fail_command("false");
+        runner.fail_command("sh -c false");
+        let report = RunReport::new();
+        config.continue_on_error = true;
+        assert!(run_custom_commands(&config, &runner, &report).is_err());
+
+        let run: Vec<String> = runner.commands().iter().map(|c| c.to_string()).collect();
+        assert_eq!(run, vec!["false", "sh -c true", "sh -c false"]);
+        let entries = report.entries();
+        let reasons: Vec<_> = entries
+            .iter()
+            .filter_map(|e| e.skip_reason.as_ref())
+            .collect();
+        assert_eq!(reasons, vec!["`true` succeeded", "`false` failed"]);
+    }
+
+    #[test]
+    fn test_probes_of_root_commands_use_sudo() {
+        let mut command = CustomCommand::new(String::from("systemctl"), None, true);
+        command.guards.unless = Some(String::from("test -f /etc/managed"));
+        command.env = Some(
+            vec![(String::from("LANG"), String::from("C"))]
+                .into_iter()
+                .collect(),
+        );
+        let mut config = test_config(false);
+        config.custom_commands = Some(vec![command]);
+        config.command_sets = None;
+
+        let runner = RecordingRunner::new();
+        let report = RunReport::new();
+        assert!(run_custom_commands(&config, &runner, &report).is_ok());
+
+        let probe = &runner.commands()[0];
+        assert_eq!(probe.command, "sudo");
+        assert_eq!(
+            probe.args,
+            vec!["env", "LANG=C", "sh", "-c", "test -f /etc/managed"]
+        );
+        assert!(probe.env.is_empty());
+        assert!(runner.root_requests() > 0);
+    }
+
+    #[test]
+    fn test_failure_stops_remaining_commands() {
+        let runner = RecordingRunner::new();
+        runner.fail_command("false");
+        let result = run_custom_commands(&test_config(false), &runner, &RunReport::new());
+        assert!(result.is_err());
+        assert_eq!(runner.commands().len(), 1);
+    }
+
+    #[test]
+    fn test_continue_on_error_per_command() {
+        let runner = RecordingRunner::new();
+        runner.fail_command("false");
+        let result = run_custom_commands(&test_config(true), &runner, &RunReport::new());
+        // The command set's command is not allowed to fail, so it stops the run with both errors
+        match result {
+            Err(Error::Stopped(errors)) => assert_eq!(errors.len(), 2),
+            other => panic!("Unexpected result {:?}", other),
+        }
+        assert_eq!(runner.commands().len(), 3);
+    }
+
+    #[test]
+    fn test_continue_on_error_globally() {
+        let runner = RecordingRunner::new();
+        runner.fail_command("false");
+        let mut config = test_config(false);
+        config.continue_on_error = true;
+        let report = RunReport::new();
+        match run_custom_commands(&config, &runner, &report) {
+            Err(Error::Multiple(errors)) => assert_eq!(errors.len(), 2),
+            other => panic!("Unexpected result {:?}", other),
+        }
+        assert_eq!(runner.commands().len(), 3);
+        assert_eq!(report.entries().iter().filter(|e| e.failed()).count(), 2);
+    }
 }
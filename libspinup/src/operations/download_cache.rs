@@ -0,0 +1,192 @@
+//! The download_cache module keeps a small manifest in each download directory,
+//! recording where every file came from and the validators the server sent
+//! with it. These are used to make conditional requests, so files that have not
+//! changed since the last run are not downloaded again.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::configuration::FileDownloadDefinition;
+use crate::error::Result;
+
+use super::checksum::verify_file;
+
+/// The name of the manifest file written to each download directory
+pub(crate) const MANIFEST_NAME: &str = ".spinup-downloads.json";
+
+/// What was recorded about a single downloaded file
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub(crate) struct CacheEntry {
+    /// The URL the file was downloaded from
+    pub source: String,
+
+    /// The `ETag` header sent with the file
+    pub etag: Option<String>,
+
+    /// The `Last-Modified` header sent with the file
+    pub last_modified: Option<String>,
+
+    /// The size of the file in bytes
+    pub size: u64,
+
+    /// The hex SHA-256 digest of the file
+    pub sha256: String,
+}
+
+/// Every file downloaded into a directory, keyed by its target name
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub(crate) struct CacheManifest {
+    files: BTreeMap<String, CacheEntry>,
+}
+
+impl CacheManifest {
+    /// Read the manifest in `dir`, starting a new one if there is none or it can't be read
+    pub(crate) fn load(dir: &Path) -> CacheManifest {
+        let path = dir.join(MANIFEST_NAME);
+        match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|e| {
+                warn!(
+                    "Ignoring the unreadable download cache {}: {}",
+                    path.display(),
+                    e
+                );
+                CacheManifest::default()
+            }),
+            Err(_) => CacheManifest::default(),
+        }
+    }
+
+    /// Record `entries` in the manifest in `dir`.
+    ///
+    /// The manifest is read again first, as other operations may share the directory.
+    pub(crate) fn update(dir: &Path, entries: Vec<(String, CacheEntry)>) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let mut manifest = CacheManifest::load(dir);
+        manifest.files.extend(entries);
+        fs::write(
+            dir.join(MANIFEST_NAME),
+            serde_json::to_string_pretty(&manifest)?,
+        )?;
+        Ok(())
+    }
+
+    /// The entry for `definition`, as long as it was downloaded from the same source and the
+    /// file at `file_path` is still exactly what was downloaded.
+    ///
+    /// A file that does not match the digests now set on `definition` has no entry, so that
+    /// it is downloaded and verified again rather than kept as unchanged.
+    pub(crate) fn entry_for(
+        &self,
+        definition: &FileDownloadDefinition,
+        file_path: &Path,
+    ) -> Option<&CacheEntry> {
+        let entry = self
+            .files
+            .get(&definition.target)
+            .filter(|entry| entry.source == definition.source)?;
+        match verify_file(definition, file_path) {
+            Ok(verified) if verified.size == entry.size && verified.sha256 == entry.sha256 => {
+                Some(entry)
+            }
+            Ok(_) => {
+                debug!(
+                    "{} has changed since it was downloaded",
+                    file_path.display()
+                );
+                None
+            }
+            Err(e) => {
+                debug!(
+                    "Not using the download cache for {}: {}",
+                    definition.target, e
+                );
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    fn definition(source: &str) -> FileDownloadDefinition {
+        serde_json::from_str(&format!(
+            r#"{{ "source": "{}", "target": "hello.txt" }}"#,
+            source
+        ))
+        .unwrap()
+    }
+
+    fn entry() -> CacheEntry {
+        CacheEntry {
+            source: String::from("https://example.com/hello"),
+            etag: Some(String::from("\"abc\"")),
+            last_modified: None,
+            size: 5,
+            sha256: String::from(HELLO_SHA256),
+        }
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(CacheManifest::load(dir.path()), CacheManifest::default());
+
+        CacheManifest::update(dir.path(), vec![(String::from("hello.txt"), entry())]).unwrap();
+        CacheManifest::update(dir.path(), vec![(String::from("other"), entry())]).unwrap();
+        let manifest = CacheManifest::load(dir.path());
+        assert_eq!(manifest.files.len(), 2);
+        assert_eq!(manifest.files["hello.txt"], entry());
+    }
+
+    #[test]
+    fn test_entry_only_used_for_unchanged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("hello.txt");
+        CacheManifest::update(dir.path(), vec![(String::from("hello.txt"), entry())]).unwrap();
+        let manifest = CacheManifest::load(dir.path());
+        let same_source = definition("https://example.com/hello");
+
+        assert!(manifest.entry_for(&same_source, &file_path).is_none());
+
+        fs::write(&file_path, b"hello").unwrap();
+        assert_eq!(manifest.entry_for(&same_source, &file_path), Some(&entry()));
+        let moved = definition("https://example.com/moved");
+        assert!(manifest.entry_for(&moved, &file_path).is_none());
+
+        fs::write(&file_path, b"HELLO").unwrap();
+        assert!(manifest.entry_for(&same_source, &file_path).is_none());
+    }
+
+    #[test]
+    fn test_entry_not_used_when_the_checksum_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("hello.txt");
+        fs::write(&file_path, b"hello").unwrap();
+        CacheManifest::update(dir.path(), vec![(String::from("hello.txt"), entry())]).unwrap();
+        let manifest = CacheManifest::load(dir.path());
+
+        let mut definition = definition("https://example.com/hello");
+        definition.sha256 = Some(String::from(HELLO_SHA256));
+        assert_eq!(manifest.entry_for(&definition, &file_path), Some(&entry()));
+
+        definition.sha256 = Some(format!("{}00", &HELLO_SHA256[..62]));
+        assert!(manifest.entry_for(&definition, &file_path).is_none());
+    }
+
+    #[test]
+    fn test_corrupt_manifest_is_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(MANIFEST_NAME), b"not json").unwrap();
+        assert_eq!(CacheManifest::load(dir.path()), CacheManifest::default());
+    }
+}
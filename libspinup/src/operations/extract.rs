@@ -0,0 +1,389 @@
+//! The extract module unpacks downloaded archives, as described by their
+//! [`ExtractDefinition`](../../configuration/struct.ExtractDefinition.html).
+//!
+//! Entries are never written outside of the destination directory, any entry
+//! with an absolute path or a `..` component, or that would be written through
+//! a symlink leading out of it, fails the extraction.
+
+use std::fs;
+use std::fs::{File, Permissions};
+use std::io;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+
+use flate2::read::GzDecoder;
+use xz2::read::XzDecoder;
+use zip::ZipArchive;
+
+use crate::configuration::{
+    ArchiveFormat, ExtractDefinition, FileDownloadDefinition, OverwritePolicy,
+};
+use crate::error::{Error, Result};
+
+/// The format of the archive downloaded by `definition`, either as configured or guessed from its target
+pub(crate) fn archive_format(
+    definition: &FileDownloadDefinition,
+    extract: &ExtractDefinition,
+) -> Result<ArchiveFormat> {
+    extract
+        .format
+        .or_else(|| ArchiveFormat::from_file_name(&definition.target))
+        .ok_or_else(|| {
+            Error::from(format!(
+                "Unable to tell the archive format of {}, set `extract.format`",
+                definition.target
+            ))
+        })
+}
+
+/// Extract the `format` archive at `archive` into `dest`, creating it if needed
+pub(crate) fn extract_archive(
+    archive: &Path,
+    format: ArchiveFormat,
+    extract: &ExtractDefinition,
+    dest: &Path,
+) -> Result<()> {
+    debug!("Extracting {:?} into {:?}", archive, dest);
+    let result = File::open(archive).map_err(Error::from).and_then(|file| {
+        fs::create_dir_all(dest)?;
+        match format {
+            ArchiveFormat::TarGz => extract_tar(GzDecoder::new(file), extract, dest),
+            ArchiveFormat::TarXz => extract_tar(XzDecoder::new(file), extract, dest),
+            ArchiveFormat::Zip => extract_zip(file, extract, dest),
+        }
+    });
+
+    result.map_err(|e| Error::from(format!("Unable to extract {}: {}", archive.display(), e)))
+}
+
+fn extract_tar<R: Read>(reader: R, extract: &ExtractDefinition, dest: &Path) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    let root = dest.canonicalize()?;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let target = match entry_target(&entry.path()?, extract.strip_components, dest)? {
+            Some(target) => target,
+            None => continue,
+        };
+        ensure_inside(&target, &root)?;
+
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_dir() {
+            fs::create_dir_all(&target)?;
+            continue;
+        }
+        if !prepare_entry(&target, extract.overwrite)? {
+            continue;
+        }
+
+        if entry_type.is_hard_link() {
+            // Hard links name another entry in the archive, which has to be found the same way
+            let link = entry
+                .link_name()?
+                .ok_or_else(|| Error::from("Hard link without a target"))?;
+            match entry_target(&link, extract.strip_components, dest)? {
+                Some(source) => {
+                    ensure_inside(&source, &root)?;
+                    fs::hard_link(source, &target)?
+                }
+                None => warn!("Skipping a hard link to {}", link.display()),
+            }
+        } else {
+            entry.unpack(&target)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_zip(file: File, extract: &ExtractDefinition, dest: &Path) -> Result<()> {
+    let mut archive = ZipArchive::new(file).map_err(zip_error)?;
+    let root = dest.canonicalize()?;
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(zip_error)?;
+        let name = PathBuf::from(entry.name());
+        let target = match entry_target(&name, extract.strip_components, dest)? {
+            Some(target) => target,
+            None => continue,
+        };
+        ensure_inside(&target, &root)?;
+
+        if entry.is_dir() {
+            fs::create_dir_all(&target)?;
+            continue;
+        }
+        if !prepare_entry(&target, extract.overwrite)? {
+            continue;
+        }
+
+        io::copy(&mut entry, &mut File::create(&target)?)?;
+        if let Some(mode) = entry.unix_mode() {
+            fs::set_permissions(&target, Permissions::from_mode(mode & 0o7777))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn zip_error(err: zip::result::ZipError) -> Error {
+    match err {
+        zip::result::ZipError::Io(e) => Error::from(e),
+        other => Error::from(other.to_string()),
+    }
+}
+
+/// Resolve where the archive entry at `path` should be written inside of `dest`, after removing
+/// `strip_components` leading components. `None` means nothing is left of the path after stripping.
+fn entry_target(path: &Path, strip_components: usize, dest: &Path) -> Result<Option<PathBuf>> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            _ => {
+                return Err(Error::from(format!(
+                    "Refusing to extract {} outside of {}",
+                    path.display(),
+                    dest.display()
+                )))
+            }
+        }
+    }
+
+    let stripped: PathBuf = relative.components().skip(strip_components).collect();
+    if stripped.as_os_str().is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(dest.join(stripped)))
+    }
+}
+
+/// Make sure that writing `target` stays inside of the canonical `root`, as one of the directories
+/// leading to it could be a symlink, such as one extracted earlier from the same archive
+fn ensure_inside(target: &Path, root: &Path) -> Result<()> {
+    for dir in target.ancestors().skip(1) {
+        // Directories that do not exist yet are created inside of the nearest one that does
+        if fs::symlink_metadata(dir).is_err() {
+            continue;
+        }
+        return match dir.canonicalize() {
+            Ok(real) if real.starts_with(root) => Ok(()),
+            _ => Err(Error::from(format!(
+                "Refusing to extract {} through a link outside of {}",
+                target.display(),
+                root.display()
+            ))),
+        };
+    }
+
+    Ok(())
+}
+
+/// Get `target` ready to be written according to `overwrite`, returning whether it should be written
+fn prepare_entry(target: &Path, overwrite: OverwritePolicy) -> Result<bool> {
+    if let Ok(existing) = fs::symlink_metadata(target) {
+        match overwrite {
+            OverwritePolicy::Skip => {
+                debug!("{} already exists, skipping", target.display());
+                return Ok(false);
+            }
+            OverwritePolicy::Error => {
+                return Err(Error::from(format!("{} already exists", target.display())));
+            }
+            OverwritePolicy::Replace if existing.is_dir() => fs::remove_dir_all(target)?,
+            OverwritePolicy::Replace => fs::remove_file(target)?,
+        }
+    }
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use xz2::write::XzEncoder;
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    use super::*;
+
+    const FILES: &[(&str, &[u8], u32)] = &[
+        ("nvim/bin/nvim", b"binary", 0o755),
+        ("nvim/share/readme.txt", b"docs", 0o644),
+    ];
+
+    fn tarball<W: Write>(writer: W) -> W {
+        let mut builder = tar::Builder::new(writer);
+        for (path, data, mode) in FILES {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(*mode);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn write_archive(dir: &Path, format: ArchiveFormat) -> PathBuf {
+        let path = dir.join("archive");
+        let file = File::create(&path).unwrap();
+        match format {
+            ArchiveFormat::TarGz => {
+                tarball(GzEncoder::new(file, Compression::default()))
+                    .finish()
+                    .unwrap();
+            }
+            ArchiveFormat::TarXz => {
+                tarball(XzEncoder::new(file, 6)).finish().unwrap();
+            }
+            ArchiveFormat::Zip => {
+                let mut zip = ZipWriter::new(file);
+                for (path, data, mode) in FILES {
+                    zip.start_file(*path, FileOptions::default().unix_permissions(*mode))
+                        .unwrap();
+                    zip.write_all(data).unwrap();
+                }
+                zip.finish().unwrap();
+            }
+        }
+        path
+    }
+
+    fn extract(
+        format: ArchiveFormat,
+        extract: &ExtractDefinition,
+    ) -> (tempfile::TempDir, Result<()>) {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = write_archive(dir.path(), format);
+        let dest = dir.path().join("out");
+        fs::create_dir_all(dest.join("bin")).unwrap();
+        fs::write(dest.join("bin/nvim"), b"old").unwrap();
+        let result = extract_archive(&archive, format, extract, &dest);
+        (dir, result)
+    }
+
+    #[test]
+    fn test_extract_each_format_with_strip_components() {
+        let definition = ExtractDefinition {
+            strip_components: 1,
+            ..ExtractDefinition::default()
+        };
+        for format in &[
+            ArchiveFormat::TarGz,
+            ArchiveFormat::TarXz,
+            ArchiveFormat::Zip,
+        ] {
+            let (dir, result) = extract(*format, &definition);
+            assert!(result.is_ok(), "{:?}: {:?}", format, result);
+            let out = dir.path().join("out");
+            assert_eq!(fs::read(out.join("bin/nvim")).unwrap(), b"binary");
+            assert_eq!(fs::read(out.join("share/readme.txt")).unwrap(), b"docs");
+            let mode = fs::metadata(out.join("bin/nvim"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o755, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_overwrite_policies() {
+        let skip = ExtractDefinition {
+            strip_components: 1,
+            overwrite: OverwritePolicy::Skip,
+            ..ExtractDefinition::default()
+        };
+        let (dir, result) = extract(ArchiveFormat::TarGz, &skip);
+        assert!(result.is_ok());
+        let out = dir.path().join("out");
+        assert_eq!(fs::read(out.join("bin/nvim")).unwrap(), b"old");
+        assert!(out.join("share/readme.txt").exists());
+
+        let error = ExtractDefinition {
+            overwrite: OverwritePolicy::Error,
+            ..skip
+        };
+        let (_dir, result) = extract(ArchiveFormat::Zip, &error);
+        assert!(result.unwrap_err().to_string().contains("already exists"));
+    }
+
+    #[test]
+    fn test_symlinks_cannot_lead_outside_of_dest() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = dir.path().join("outside");
+        fs::create_dir(&outside).unwrap();
+
+        let path = dir.path().join("archive");
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            File::create(&path).unwrap(),
+            Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder.append_link(&mut header, "etc", &outside).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "etc/passwd", &b"evil"[..])
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let dest = dir.path().join("out");
+        let result = extract_archive(
+            &path,
+            ArchiveFormat::TarGz,
+            &ExtractDefinition::default(),
+            &dest,
+        );
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Refusing to extract"));
+        assert!(!outside.join("passwd").exists());
+        assert!(fs::symlink_metadata(dest.join("etc"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+    }
+
+    #[test]
+    fn test_entry_target() {
+        let dest = Path::new("/opt/tools");
+        assert_eq!(
+            entry_target(Path::new("./nvim/bin/nvim"), 1, dest).unwrap(),
+            Some(PathBuf::from("/opt/tools/bin/nvim"))
+        );
+        assert_eq!(entry_target(Path::new("nvim/"), 1, dest).unwrap(), None);
+        assert!(entry_target(Path::new("../etc/passwd"), 0, dest).is_err());
+        assert!(entry_target(Path::new("/etc/passwd"), 0, dest).is_err());
+    }
+
+    #[test]
+    fn test_archive_format_is_guessed_from_target() {
+        let mut definition: FileDownloadDefinition = serde_json::from_str(
+            r#"{ "source": "https://example.com/dl", "target": "nvim.tar.gz", "extract": {} }"#,
+        )
+        .unwrap();
+        let extract = definition.extract.take().unwrap();
+        assert_eq!(
+            archive_format(&definition, &extract).unwrap(),
+            ArchiveFormat::TarGz
+        );
+
+        definition.target = String::from("nvim");
+        assert!(archive_format(&definition, &extract).is_err());
+    }
+}
@@ -0,0 +1,210 @@
+//! The github_release module finds the download URL of a file attached to a
+//! GitHub release, as described by a [`GithubRelease`](../../configuration/struct.GithubRelease.html).
+//!
+//! The release is read from the releases API when the download starts, so the
+//! newest version of a tool can be downloaded without changing the configuration.
+
+use glob::Pattern;
+use reqwest::header::{ACCEPT, USER_AGENT};
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+
+use crate::configuration::{GithubRelease, SystemDetails};
+use crate::error::{Error, Result};
+
+const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// The parts of a release from the API that are needed to pick an asset
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// The API URL that describes `release`
+pub(crate) fn release_url(release: &GithubRelease) -> Result<String> {
+    let repo = release.repo.trim_matches('/');
+    if repo.split('/').count() != 2 || repo.split('/').any(str::is_empty) {
+        return Err(Error::from(format!(
+            "`{}` is not a GitHub repository, it should be written as `owner/repo`",
+            release.repo
+        )));
+    }
+
+    let api_url = release
+        .api_url
+        .as_deref()
+        .unwrap_or(DEFAULT_API_URL)
+        .trim_end_matches('/');
+    Ok(match &release.tag[..] {
+        "latest" => format!("{}/repos/{}/releases/latest", api_url, repo),
+        tag => format!("{}/repos/{}/releases/tags/{}", api_url, repo, tag),
+    })
+}
+
+/// Send `request`, made for the [`release_url`](fn.release_url.html) of `release`, and return
+/// the download URL of the one asset that matches its pattern on this system
+pub(crate) async fn resolve_asset(
+    release: &GithubRelease,
+    system_details: &SystemDetails,
+    request: RequestBuilder,
+) -> Result<String> {
+    let body = request
+        .header(ACCEPT, "application/vnd.github.v3+json")
+        .header(USER_AGENT, concat!("spinup/", env!("CARGO_PKG_VERSION")))
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    let found: Release = serde_json::from_slice(&body)?;
+    pick_asset(release, system_details, found)
+}
+
+/// The pattern of `release` with its placeholders filled in for this system
+fn asset_pattern(release: &GithubRelease, system_details: &SystemDetails) -> String {
+    release
+        .asset
+        .replace("{arch}", system_details.arch())
+        .replace("{os}", system_details.os())
+}
+
+fn pick_asset(
+    release: &GithubRelease,
+    system_details: &SystemDetails,
+    found: Release,
+) -> Result<String> {
+    let pattern = asset_pattern(release, system_details);
+    let glob = Pattern::new(&pattern)
+        .map_err(|e| Error::from(format!("`{}` is not a valid asset pattern: {}", pattern, e)))?;
+    let names = || {
+        found
+            .assets
+            .iter()
+            .map(|asset| &asset.name[..])
+            .collect::<Vec<&str>>()
+            .join(", ")
+    };
+
+    let mut matching = found
+        .assets
+        .iter()
+        .filter(|asset| glob.matches(&asset.name));
+    match (matching.next(), matching.next()) {
+        (Some(asset), None) => {
+            debug!(
+                "Using {} from {} {}",
+                asset.name, release.repo, found.tag_name
+            );
+            Ok(asset.browser_download_url.clone())
+        }
+        (None, _) => Err(Error::from(format!(
+            "No asset of {} {} matches `{}`, it has: {}",
+            release.repo,
+            found.tag_name,
+            pattern,
+            names()
+        ))),
+        (Some(_), Some(_)) => Err(Error::from(format!(
+            "More than one asset of {} {} matches `{}`, it has: {}",
+            release.repo,
+            found.tag_name,
+            pattern,
+            names()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::TargetOperatingSystem;
+
+    fn release(tag: &str, asset: &str) -> GithubRelease {
+        GithubRelease {
+            repo: String::from("BurntSushi/ripgrep"),
+            tag: String::from(tag),
+            asset: String::from(asset),
+            api_url: None,
+        }
+    }
+
+    fn found(names: &[&str]) -> Release {
+        Release {
+            tag_name: String::from("12.1.1"),
+            assets: names
+                .iter()
+                .map(|name| Asset {
+                    name: String::from(*name),
+                    browser_download_url: format!("https://example.com/{}", name),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_release_url() {
+        assert_eq!(
+            release_url(&release("latest", "*")).unwrap(),
+            "https://api.github.com/repos/BurntSushi/ripgrep/releases/latest"
+        );
+        let enterprise = GithubRelease {
+            api_url: Some(String::from("https://git.example.com/api/v3/")),
+            ..release("12.1.1", "*")
+        };
+        assert_eq!(
+            release_url(&enterprise).unwrap(),
+            "https://git.example.com/api/v3/repos/BurntSushi/ripgrep/releases/tags/12.1.1"
+        );
+        let bad_repo = GithubRelease {
+            repo: String::from("ripgrep"),
+            ..release("latest", "*")
+        };
+        assert!(release_url(&bad_repo).is_err());
+    }
+
+    #[test]
+    fn test_placeholders_pick_the_asset_for_this_system() {
+        let system = SystemDetails::new(TargetOperatingSystem::Debian);
+        let names = [
+            format!(
+                "ripgrep-12.1.1-{}-unknown-{}-musl.tar.gz",
+                system.arch(),
+                system.os()
+            ),
+            String::from("ripgrep-12.1.1-sparc-unknown-plan9-musl.tar.gz"),
+        ];
+        let names: Vec<&str> = names.iter().map(|n| &n[..]).collect();
+
+        let url = pick_asset(
+            &release("latest", "ripgrep-*-{arch}-unknown-{os}-musl.tar.gz"),
+            &system,
+            found(&names),
+        )
+        .unwrap();
+        assert_eq!(url, format!("https://example.com/{}", names[0]));
+    }
+
+    #[test]
+    fn test_asset_must_match_exactly_once() {
+        let system = SystemDetails::new(TargetOperatingSystem::Debian);
+        let found_names = &["rg.tar.gz", "rg.zip"];
+
+        let none = pick_asset(&release("latest", "*.deb"), &system, found(found_names));
+        assert_eq!(
+            none.unwrap_err().to_string(),
+            "No asset of BurntSushi/ripgrep 12.1.1 matches `*.deb`, it has: rg.tar.gz, rg.zip"
+        );
+        let many = pick_asset(&release("latest", "rg.*"), &system, found(found_names));
+        assert!(many
+            .unwrap_err()
+            .to_string()
+            .starts_with("More than one asset"));
+    }
+}
@@ -1,22 +1,178 @@
 //! The operations module defines various operations that can be run
 //! and also provides the means to run them.
 
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::fmt;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
 use libc;
+use serde::Serialize;
 
-use crate::configuration::SystemDetails;
-use crate::error::Result;
+use crate::configuration::{CommandGuards, Condition, SystemDetails, DEFAULT_SHELL};
+use crate::error::{Error, Result};
 
+mod checksum;
 mod custom_commands;
+mod download_cache;
+mod extract;
 mod file_downloads;
+mod github_release;
 mod packages;
+mod plan;
+mod report;
 mod runcore;
 mod snap;
 
 pub use custom_commands::run_custom_commands;
 pub use file_downloads::execute_download_operations;
 pub use packages::install_packages;
+pub use plan::{build_plan, Plan, PlanStep};
+pub use report::{ReportEntry, ReportSummary, RunReport};
+pub use runcore::{CommandOutput, CommandRunner, ProcessRunner, RecordingRunner};
 pub use snap::install_snap_packages;
 
+/// The phases of a run, in the order that [`run_app`](../fn.run_app.html) executes them
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Refreshing package lists and upgrading the installed packages
+    SystemUpgrade,
+
+    /// Installing the configured [`PackageList`](../configuration/struct.PackageList.html)
+    Packages,
+
+    /// Downloading files and running their `after_complete` commands
+    FileDownloads,
+
+    /// Running the standalone custom commands
+    CustomCommands,
+
+    /// Running each command set in order
+    CommandSets,
+
+    /// Installing snap packages
+    Snaps,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Phase::SystemUpgrade => "system_upgrade",
+            Phase::Packages => "packages",
+            Phase::FileDownloads => "file_downloads",
+            Phase::CustomCommands => "custom_commands",
+            Phase::CommandSets => "command_sets",
+            Phase::Snaps => "snaps",
+        };
+
+        write!(f, "{}", text)
+    }
+}
+
+/// The final command line for a [`RunnableOperation`](trait.RunnableOperation.html),
+/// after `sudo` has been applied when needed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ResolvedCommand {
+    /// The executable that will be spawned
+    pub command: String,
+
+    /// The arguments passed to [`command`](struct.ResolvedCommand.html#structfield.command)
+    pub args: Vec<String>,
+
+    /// The directory to run the command in, instead of the current one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+
+    /// Environment variables to set for the command
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+
+    /// Whether the command starts without any of the environment of this process
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub clear_env: bool,
+
+    /// How long the command may run for before it is stopped
+    #[serde(skip)]
+    pub timeout: Option<Duration>,
+
+    /// What each line of output is prefixed with when it is shown while the command runs,
+    /// such as `[packages] apt-get`. Commands without a label never have their output shown
+    #[serde(skip)]
+    pub label: Option<String>,
+}
+
+impl ResolvedCommand {
+    /// A command that runs `command` with `args`, in the current directory and environment
+    pub fn new(command: &str, args: &[String]) -> Self {
+        ResolvedCommand {
+            command: command.to_string(),
+            args: args.to_vec(),
+            ..ResolvedCommand::default()
+        }
+    }
+
+    /// This command run through `sudo`, with its environment passed on through `env`
+    /// as `sudo` would otherwise reset it
+    pub(crate) fn with_sudo(self) -> Self {
+        let mut args = Vec::new();
+        if self.clear_env || !self.env.is_empty() {
+            args.push(String::from("env"));
+            if self.clear_env {
+                args.push(String::from("-i"));
+            }
+            args.extend(
+                self.env
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value)),
+            );
+        }
+        args.push(self.command);
+        args.extend(self.args);
+
+        ResolvedCommand {
+            command: String::from("sudo"),
+            args,
+            cwd: self.cwd,
+            timeout: self.timeout,
+            label: self.label,
+            ..ResolvedCommand::default()
+        }
+    }
+}
+
+impl fmt::Display for ResolvedCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.clear_env {
+            write!(f, "env -i ")?;
+        }
+        for (name, value) in &self.env {
+            write_word(f, &format!("{}={}", name, value))?;
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.command)?;
+        for arg in &self.args {
+            write!(f, " ")?;
+            write_word(f, arg)?;
+        }
+        if let Some(cwd) = &self.cwd {
+            write!(f, " (in {})", cwd.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Write `word`, quoted if it is empty or contains whitespace
+fn write_word(f: &mut fmt::Formatter, word: &str) -> fmt::Result {
+    if word.is_empty() || word.contains(char::is_whitespace) {
+        write!(f, "{:?}", word)
+    } else {
+        write!(f, "{}", word)
+    }
+}
+
 /// The `RunnableOperation` trait represents those operations that will
 /// be executed as shell processes. This includes package installs,
 /// snap & flatpak packages, custom commands, and so on.
@@ -32,14 +188,195 @@ pub trait RunnableOperation {
     /// It is important that this is only a single string of the actual command
     /// be included in [`args`](trait.RunnableOperation.html#tymethod.args)
     /// to run, with no arguments. If there are additional sub-commands they should
-    fn command_name(&self, system_details: SystemDetails) -> Result<String>;
+    fn command_name(&self, system_details: &SystemDetails) -> Result<String>;
 
     /// Any additional arguments to be sent to the command that will be run.
     /// This includes subcommands, arguments, etc.
-    fn args(&self, system_details: SystemDetails) -> Option<Vec<String>>;
+    fn args(&self, system_details: &SystemDetails) -> Option<Vec<String>>;
 
     /// Whether this process requires root permissions (via `sudo`) to run
-    fn needs_root(&self) -> bool;
+    fn needs_root(&self, system_details: &SystemDetails) -> bool;
+
+    /// Whether the run should carry on if this operation fails
+    fn continue_on_error(&self) -> bool {
+        false
+    }
+
+    /// Why this operation should be skipped on the current system, or `None` if it should run
+    fn skip_reason(&self, _system_details: &SystemDetails) -> Option<String> {
+        None
+    }
+
+    /// The directory to run the command in, when it should not be the current one
+    fn working_dir(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Environment variables to set for the command
+    fn env(&self) -> Option<&BTreeMap<String, String>> {
+        None
+    }
+
+    /// Whether the command should start without any of the environment of this process
+    fn clear_env(&self) -> bool {
+        false
+    }
+
+    /// The checks that can skip this operation just before it runs
+    fn guards(&self) -> Option<&CommandGuards> {
+        None
+    }
+
+    /// How long the command may run for before it is stopped, `None` for no limit
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// How many times to run the command again after it fails
+    fn retries(&self) -> u32 {
+        0
+    }
+
+    /// How long to wait before the first retry, this doubles after each one
+    fn retry_delay(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+}
+
+/// Wraps an operation so that it is also skipped when an outer condition,
+/// such as the one on its command set, does not hold.
+pub(crate) struct Conditional<'a> {
+    operation: &'a dyn RunnableOperation,
+    when: Option<&'a Condition>,
+}
+
+impl<'a> Conditional<'a> {
+    pub(crate) fn new(operation: &'a dyn RunnableOperation, when: Option<&'a Condition>) -> Self {
+        Conditional { operation, when }
+    }
+}
+
+impl<'a> RunnableOperation for Conditional<'a> {
+    fn command_name(&self, system_details: &SystemDetails) -> Result<String> {
+        self.operation.command_name(system_details)
+    }
+
+    fn args(&self, system_details: &SystemDetails) -> Option<Vec<String>> {
+        self.operation.args(system_details)
+    }
+
+    fn needs_root(&self, system_details: &SystemDetails) -> bool {
+        self.operation.needs_root(system_details)
+    }
+
+    fn continue_on_error(&self) -> bool {
+        self.operation.continue_on_error()
+    }
+
+    fn skip_reason(&self, system_details: &SystemDetails) -> Option<String> {
+        self.when
+            .and_then(|when| when.unmet(system_details))
+            .or_else(|| self.operation.skip_reason(system_details))
+    }
+
+    fn working_dir(&self) -> Option<PathBuf> {
+        self.operation.working_dir()
+    }
+
+    fn env(&self) -> Option<&BTreeMap<String, String>> {
+        self.operation.env()
+    }
+
+    fn clear_env(&self) -> bool {
+        self.operation.clear_env()
+    }
+
+    fn guards(&self) -> Option<&CommandGuards> {
+        self.operation.guards()
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        self.operation.timeout()
+    }
+
+    fn retries(&self) -> u32 {
+        self.operation.retries()
+    }
+
+    fn retry_delay(&self) -> Duration {
+        self.operation.retry_delay()
+    }
+}
+
+/// Collects the failures of operations that are allowed to fail without
+/// stopping the rest of the run.
+///
+/// A set of operations that ran to completion returns its failures as an
+/// [`Error::Multiple`](../error/enum.Error.html#variant.Multiple). A failure that is not allowed
+/// stops the run, and is returned on its own, or as an
+/// [`Error::Stopped`](../error/enum.Error.html#variant.Stopped) along with the failures before it.
+#[derive(Debug, Default)]
+pub(crate) struct Failures {
+    errors: Vec<Error>,
+}
+
+impl Failures {
+    /// Keep the error in `result` and carry on when `continue_on_error` is set.
+    /// Otherwise the error stops the run, and is returned along with the failures collected so far.
+    pub(crate) fn allow(&mut self, result: Result<()>, continue_on_error: bool) -> Result<()> {
+        match result {
+            Err(Error::Multiple(errors)) if continue_on_error => {
+                self.errors.extend(errors);
+                Ok(())
+            }
+            Err(e) if continue_on_error => {
+                warn!("Continuing after failure: {}", e);
+                self.errors.push(e);
+                Ok(())
+            }
+            Err(e) => Err(self.stop(e)),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// Keep the failures from a set of operations that ran to completion, as returned
+    /// from [`into_result`](#method.into_result). Any other error stops the run, and is
+    /// returned along with the failures collected so far.
+    pub(crate) fn gather(&mut self, result: Result<()>) -> Result<()> {
+        match result {
+            Ok(()) => Ok(()),
+            Err(Error::Multiple(errors)) => {
+                self.errors.extend(errors);
+                Ok(())
+            }
+            Err(e) => Err(self.stop(e)),
+        }
+    }
+
+    /// The error that stops the run at `e`, including every failure collected so far
+    fn stop(&mut self, e: Error) -> Error {
+        match e {
+            Error::Stopped(errors) | Error::Multiple(errors) => self.errors.extend(errors),
+            e if self.errors.is_empty() => return e,
+            e => self.errors.push(e),
+        }
+        Error::Stopped(self.errors.drain(..).collect())
+    }
+
+    /// Whether nothing has failed so far
+    pub(crate) fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Finish collecting, returning an [`Error::Multiple`](../error/enum.Error.html#variant.Multiple)
+    /// if anything failed.
+    pub(crate) fn into_result(self) -> Result<()> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Multiple(self.errors))
+        }
+    }
 }
 
 /// Helper function that queries `libc` to check whether we're inside a
@@ -51,42 +388,202 @@ pub(crate) fn process_is_root() -> bool {
     }
 }
 
-/// Run the given `RunnableOperation`, returning an empty result if there were no errors
+/// The name of the user this process runs as, if it can be found
+pub(crate) fn current_user_name() -> Option<String> {
+    // Safe, as `geteuid` can't fail
+    user_name(unsafe { libc::geteuid() })
+}
+
+/// The name of the primary group of the user this process runs as, if it can be found
+pub(crate) fn current_group_name() -> Option<String> {
+    // Safe, as `getegid` can't fail
+    group_name(unsafe { libc::getegid() })
+}
+
+/// The name of the user with the id `uid`, if it can be found
+pub(crate) fn user_name(uid: libc::uid_t) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 4096];
+    unsafe {
+        let mut passwd: libc::passwd = std::mem::zeroed();
+        let mut result = std::ptr::null_mut();
+        libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result);
+        if result.is_null() {
+            return None;
+        }
+        Some(
+            CStr::from_ptr(passwd.pw_name)
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+/// The name of the group with the id `gid`, if it can be found
+pub(crate) fn group_name(gid: libc::gid_t) -> Option<String> {
+    let mut buf = vec![0 as libc::c_char; 4096];
+    unsafe {
+        let mut group: libc::group = std::mem::zeroed();
+        let mut result = std::ptr::null_mut();
+        libc::getgrgid_r(gid, &mut group, buf.as_mut_ptr(), buf.len(), &mut result);
+        if result.is_null() {
+            return None;
+        }
+        Some(CStr::from_ptr(group.gr_name).to_string_lossy().into_owned())
+    }
+}
+
+/// Resolve the given `RunnableOperation` into the command line that would be
+/// spawned for it, without running anything.
+///
+/// As `sudo` resets the environment, the environment of a command that needs root is set
+/// with `env` once `sudo` has started, rather than on the `sudo` process itself.
 ///
 /// # Arguments:
 ///
-/// - `runnable`: The `RunnableOperation` to execute
+/// - `runnable`: The `RunnableOperation` to resolve
 /// - `system_details`: The current configuration's system details for which system we're running in
-fn run_command(runnable: &impl RunnableOperation, system_details: SystemDetails) -> Result<()> {
-    let command_name = runnable.command_name(system_details)?;
-    let (base_command, first_arg) = {
-        if runnable.needs_root() {
-            runcore::get_root()?;
-            ("sudo", Some(&command_name[..]))
+pub(crate) fn resolve_command(
+    runnable: &dyn RunnableOperation,
+    system_details: &SystemDetails,
+) -> Result<ResolvedCommand> {
+    let resolved = ResolvedCommand {
+        command: runnable.command_name(system_details)?,
+        args: runnable.args(system_details).unwrap_or_default(),
+        cwd: runnable.working_dir(),
+        env: runnable.env().cloned().unwrap_or_default(),
+        clear_env: runnable.clear_env(),
+        timeout: runnable.timeout(),
+        label: None,
+    };
+
+    if runnable.needs_root(system_details) {
+        Ok(resolved.with_sudo())
+    } else {
+        Ok(resolved)
+    }
+}
+
+/// Describe why the [`guards`](trait.RunnableOperation.html#method.guards) of `runnable` say it
+/// should be skipped, running any probes they have with `runner`. The probes of a command
+/// that needs root run through `sudo` as well, so that they see what the command would.
+fn guard_skip_reason(
+    runnable: &dyn RunnableOperation,
+    system_details: &SystemDetails,
+    runner: &dyn CommandRunner,
+) -> Result<Option<String>> {
+    let guards = match runnable.guards() {
+        Some(guards) => guards,
+        None => return Ok(None),
+    };
+    let cwd = runnable.working_dir();
+    let needs_root = runnable.needs_root(system_details);
+    let mut probe = |check: &str| {
+        let probe = ResolvedCommand {
+            command: String::from(DEFAULT_SHELL),
+            args: vec![String::from("-c"), check.to_owned()],
+            cwd: cwd.clone(),
+            env: runnable.env().cloned().unwrap_or_default(),
+            clear_env: runnable.clear_env(),
+            timeout: runnable.timeout(),
+            label: None,
+        };
+        let probe = if needs_root {
+            runner.acquire_root()?;
+            probe.with_sudo()
         } else {
-            (&command_name[..], None)
-        }
+            probe
+        };
+        debug!("Checking guard `{}`", probe);
+        Ok(runner.spawn(&probe)?.code == Some(0))
     };
+    guards.unmet(cwd.as_deref(), &mut probe)
+}
+
+/// Run the given `RunnableOperation`, returning an empty result if there were no errors.
+/// Operations whose [`skip_reason`](trait.RunnableOperation.html#method.skip_reason) is set,
+/// or whose [`guards`](trait.RunnableOperation.html#method.guards) do not pass, are recorded
+/// as skipped instead.
+///
+/// A command that fails is run again as many times as its
+/// [`retries`](trait.RunnableOperation.html#method.retries) allow, and only the last attempt is recorded.
+///
+/// This blocks until the command and its retries are done, so async code must only call it
+/// from the blocking thread pool, as the file downloads do.
+///
+/// # Arguments:
+///
+/// - `phase`: The [`Phase`](enum.Phase.html) of the run this operation belongs to
+/// - `runnable`: The `RunnableOperation` to execute
+/// - `system_details`: The current configuration's system details for which system we're running in
+/// - `runner`: The [`CommandRunner`](trait.CommandRunner.html) that will execute the command
+/// - `report`: The [`RunReport`](struct.RunReport.html) the outcome will be recorded in
+fn run_command(
+    phase: Phase,
+    runnable: &dyn RunnableOperation,
+    system_details: &SystemDetails,
+    runner: &dyn CommandRunner,
+    report: &RunReport,
+) -> Result<()> {
+    let resolved = resolve_command(runnable, system_details)?;
 
-    let mut args: Vec<String> = Vec::new();
+    let skip_reason = match runnable.skip_reason(system_details) {
+        Some(reason) => Some(reason),
+        None => guard_skip_reason(runnable, system_details, runner)?,
+    };
+    if let Some(reason) = skip_reason {
+        info!("Skipping `{}` as {}", resolved, reason);
+        report.record_skipped(phase, resolved.to_string(), Some(resolved), reason);
+        return Ok(());
+    }
 
-    if let Some(arg) = first_arg {
-        args.push(arg.to_string());
+    if runnable.needs_root(system_details) {
+        runner.acquire_root()?;
     }
 
-    args.extend(
-        runnable
-            .args(system_details)
-            .unwrap_or_default()
-            .into_iter(),
-    );
+    let mut resolved = resolved;
+    resolved.label = Some(format!(
+        "[{}] {}",
+        phase,
+        runnable.command_name(system_details)?
+    ));
+
+    let retries = runnable.retries();
+    let mut delay = runnable.retry_delay();
+    let mut retry = 0;
+    loop {
+        let started = Instant::now();
+        let output = runner.spawn(&resolved);
+        let elapsed = started.elapsed();
+
+        let result = match &output {
+            Ok(output) => runcore::handle_process_output(&resolved.to_string(), output),
+            Err(_) => Ok(()),
+        };
+        let failure = match (&output, &result) {
+            (Err(e), _) | (_, Err(e)) => Some(e),
+            _ => None,
+        };
+        if let Some(e) = failure.filter(|_| retry < retries) {
+            retry += 1;
+            warn!(
+                "`{}` failed: {}, retrying in {:?} ({}/{})",
+                resolved, e, delay, retry, retries
+            );
+            thread::sleep(delay);
+            delay *= 2;
+            continue;
+        }
+        report.record_command(phase, &resolved, elapsed, &output, &result);
 
-    runcore::internal_runner(base_command, &args)
+        return output.and(result);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::configuration::CustomCommand;
+
     struct DummyRunnable {
         command: String,
         args: Option<Vec<String>>,
@@ -94,13 +591,13 @@ mod tests {
     }
 
     impl RunnableOperation for DummyRunnable {
-        fn command_name(&self, _system_details: SystemDetails) -> Result<String> {
+        fn command_name(&self, _system_details: &SystemDetails) -> Result<String> {
             Ok(self.command.clone())
         }
-        fn args(&self, _system_details: SystemDetails) -> Option<Vec<String>> {
+        fn args(&self, _system_details: &SystemDetails) -> Option<Vec<String>> {
             self.args.clone()
         }
-        fn needs_root(&self) -> bool {
+        fn needs_root(&self, _system_details: &SystemDetails) -> bool {
             self.root
         }
     }
@@ -112,15 +609,40 @@ mod tests {
             args: Some(vec!["one".to_string(), "two".to_string()]),
             root: false,
         };
-        let res = run_command(&runnable, SystemDetails::default());
+        let runner = RecordingRunner::new();
+        let report = RunReport::new();
+        let res = run_command(
+            Phase::CustomCommands,
+            &runnable,
+            &SystemDetails::default(),
+            &runner,
+            &report,
+        );
         assert!(res.is_ok());
-        let cmd = runcore::passed_command();
+        let cmd = runner.last_command();
         assert!(cmd.is_some());
-        assert_eq!(cmd.unwrap(), "testing".to_string());
-        let args = runcore::passed_args();
-        assert!(args.is_some());
-        assert_eq!(args.unwrap(), vec!["one".to_string(), "two".to_string()]);
-        assert!(!runcore::called_root())
+        let cmd = cmd.unwrap();
+        assert_eq!(cmd.command, "testing".to_string());
+        assert_eq!(cmd.args, vec!["one".to_string(), "two".to_string()]);
+        assert_eq!(runner.root_requests(), 0);
+    }
+
+    #[test]
+    fn test_resolve_command_root() {
+        let runnable = DummyRunnable {
+            command: "testing".to_string(),
+            args: Some(vec!["one".to_string()]),
+            root: true,
+        };
+        let actual = resolve_command(&runnable, &SystemDetails::default()).unwrap();
+        assert_eq!(actual.command, "sudo");
+        assert_eq!(actual.args, vec!["testing".to_string(), "one".to_string()]);
+    }
+
+    #[test]
+    fn test_resolved_command_display_quotes_spaces() {
+        let resolved = ResolvedCommand::new("mv", &["a file.ttf".to_string(), "b".to_string()]);
+        assert_eq!(resolved.to_string(), "mv \"a file.ttf\" b");
     }
 
     #[test]
@@ -130,17 +652,133 @@ mod tests {
             args: Some(vec!["one".to_string()]),
             root: true,
         };
-        let res = run_command(&runnable, SystemDetails::default());
+        let runner = RecordingRunner::new();
+        let report = RunReport::new();
+        let res = run_command(
+            Phase::CustomCommands,
+            &runnable,
+            &SystemDetails::default(),
+            &runner,
+            &report,
+        );
         assert!(res.is_ok());
-        let cmd = runcore::passed_command();
+        let cmd = runner.last_command();
         assert!(cmd.is_some());
-        assert_eq!(cmd.unwrap(), "sudo".to_string());
-        let args = runcore::passed_args();
-        assert!(args.is_some());
-        assert_eq!(
-            args.unwrap(),
-            vec!["testing".to_string(), "one".to_string()]
+        let cmd = cmd.unwrap();
+        assert_eq!(cmd.command, "sudo".to_string());
+        assert_eq!(cmd.args, vec!["testing".to_string(), "one".to_string()]);
+        assert_eq!(runner.root_requests(), 1);
+        assert_eq!(report.entries().len(), 1);
+        assert_eq!(report.entries()[0].operation, "sudo testing one");
+    }
+
+    #[test]
+    fn test_failures_allow_only_when_continuing() {
+        let mut failures = Failures::default();
+        assert!(failures.allow(Err(Error::from("stop")), false).is_err());
+        assert!(failures.allow(Err(Error::from("keep")), true).is_ok());
+        assert!(failures.allow(Ok(()), false).is_ok());
+        match failures.into_result() {
+            Err(Error::Multiple(errors)) => assert_eq!(errors.len(), 1),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_failures_gather_multiple_then_hard_error() {
+        let mut failures = Failures::default();
+        let gathered = failures.gather(Err(Error::Multiple(vec![
+            Error::from("one"),
+            Error::from("two"),
+        ])));
+        assert!(gathered.is_ok());
+        match failures.gather(Err(Error::from("three"))) {
+            Err(Error::Stopped(errors)) => assert_eq!(errors.len(), 3),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_failures_gather_hard_error_alone_is_unchanged() {
+        let mut failures = Failures::default();
+        match failures.gather(Err(Error::from("only"))) {
+            Err(Error::Other(msg)) => assert_eq!(msg, "only"),
+            other => panic!("Unexpected result {:?}", other),
+        }
+        assert!(failures.into_result().is_ok());
+    }
+
+    struct FailingRunner;
+
+    impl CommandRunner for FailingRunner {
+        fn spawn(&self, _command: &ResolvedCommand) -> Result<CommandOutput> {
+            Ok(CommandOutput {
+                code: Some(3),
+                stdout: Vec::new(),
+                stderr: b"broken".to_vec(),
+            })
+        }
+
+        fn acquire_root(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_run_call_failure_is_reported() {
+        let runnable = DummyRunnable {
+            command: "testing".to_string(),
+            args: None,
+            root: false,
+        };
+        let report = RunReport::new();
+        let res = run_command(
+            Phase::Snaps,
+            &runnable,
+            &SystemDetails::default(),
+            &FailingRunner,
+            &report,
+        );
+        assert!(res.is_err());
+        let entries = report.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].phase, Phase::Snaps);
+        assert_eq!(entries[0].exit_code, Some(3));
+        assert_eq!(entries[0].stderr, "broken");
+        assert!(entries[0].failed());
+    }
+
+    #[test]
+    fn test_failed_commands_are_retried() {
+        let command: CustomCommand =
+            serde_yaml::from_str("command: flaky\nretries: 2\nretry_delay: 0\ntimeout: 5\n")
+                .unwrap();
+        let resolved = resolve_command(&command, &SystemDetails::default()).unwrap();
+        assert_eq!(resolved.timeout, Some(Duration::from_secs(5)));
+
+        let runner = RecordingRunner::new();
+        runner.fail_command("flaky");
+        let report = RunReport::new();
+        let res = run_command(
+            Phase::CustomCommands,
+            &command,
+            &SystemDetails::default(),
+            &runner,
+            &report,
         );
-        assert!(runcore::called_root());
+        assert!(res.is_err());
+        assert_eq!(runner.commands().len(), 3);
+        assert_eq!(report.entries().len(), 1);
+
+        runner.reset();
+        let res = run_command(
+            Phase::CustomCommands,
+            &command,
+            &SystemDetails::default(),
+            &runner,
+            &report,
+        );
+        assert!(res.is_ok());
+        assert_eq!(runner.commands().len(), 1);
     }
 }
@@ -1,35 +1,260 @@
-use super::run_command;
+use std::time::Instant;
 
-use crate::configuration::Configuration;
-use crate::error::Result;
+use super::{
+    resolve_command, run_command, CommandRunner, Failures, Phase, RunReport, RunnableOperation,
+};
+
+use crate::configuration::{Configuration, PackageList, PackageManager, SystemDetails};
+use crate::error::{Error, Result};
 
 /// Call the system package manager to install the packages contained
 /// in the configuration.
 ///
+/// Packages that are already installed are skipped, and if every package is
+/// installed the package manager will not be called at all.
+///
 /// # Arguments
 ///
 /// * `config` - The current configuration
-pub fn install_packages(config: &Configuration) -> Result<()> {
-    upgrade_system(&config)?;
+/// * `runner` - The [`CommandRunner`](trait.CommandRunner.html) that will execute the commands
+/// * `report` - The [`RunReport`](struct.RunReport.html) that outcomes are recorded in
+pub fn install_packages(
+    config: &Configuration,
+    runner: &dyn CommandRunner,
+    report: &RunReport,
+) -> Result<()> {
+    let missing = match &config.package_list {
+        Some(packages) => remove_installed(packages, &config.system_details, runner, report),
+        None => {
+            info!("No packages were detected in the configuration file");
+            None
+        }
+    };
 
-    if let Some(packages) = &config.package_list {
-        run_command(packages, config.system_details)
-    } else {
-        info!("No packages were detected in the configuration file");
-        Ok(())
+    let mut failures = Failures::default();
+    let result = visit_package_operations(config, missing.as_ref(), &mut |phase, op| {
+        let result = run_command(phase, op, &config.system_details, runner, report);
+        failures.allow(result, config.continue_on_error || op.continue_on_error())
+    });
+    failures.gather(result)?;
+    failures.into_result()
+}
+
+/// Walk the system upgrade and package install operations in the order they
+/// should be run, passing each one to `visit`.
+///
+/// `packages` is the list of packages to install, which may differ from the
+/// configured list when some of them are already installed.
+pub(crate) fn visit_package_operations(
+    config: &Configuration,
+    packages: Option<&PackageList>,
+    visit: &mut dyn FnMut(Phase, &dyn RunnableOperation) -> Result<()>,
+) -> Result<()> {
+    upgrade_system(config, visit)?;
+
+    match packages {
+        Some(packages) => visit(Phase::Packages, packages),
+        None => Ok(()),
     }
 }
 
-fn upgrade_system(config: &Configuration) -> Result<()> {
+fn upgrade_system(
+    config: &Configuration,
+    visit: &mut dyn FnMut(Phase, &dyn RunnableOperation) -> Result<()>,
+) -> Result<()> {
     if !config.update_system {
         return Ok(());
     }
 
     let package_manager = config.system_details.package_manager();
+    if !package_manager.can_upgrade() {
+        return Err(Error::from(
+            "Cannot run update/upgrade operations on this platform",
+        ));
+    }
 
     if let Some(ref update_cmd) = package_manager.update_operation() {
-        run_command(update_cmd, config.system_details)?;
+        visit(Phase::SystemUpgrade, update_cmd)?;
+    }
+
+    visit(Phase::SystemUpgrade, &package_manager.upgrade_operation())
+}
+
+/// Query the package manager for every package in `packages`, returning a list
+/// of only those that still need to be installed. When every package is already
+/// installed this returns `None`, and the skipped install is recorded in `report`.
+fn remove_installed(
+    packages: &PackageList,
+    system_details: &SystemDetails,
+    runner: &dyn CommandRunner,
+    report: &RunReport,
+) -> Option<PackageList> {
+    let wanted = packages.packages_for(system_details);
+    if wanted.is_empty() {
+        // Let the install report that there is nothing listed
+        return Some(packages.clone());
+    }
+
+    let package_manager = system_details.package_manager();
+    let (installed, missing): (Vec<String>, Vec<String>) = wanted
+        .into_iter()
+        .partition(|p| is_installed(&package_manager, p, system_details, runner, report));
+
+    if !installed.is_empty() {
+        info!("Already installed: {}", installed.join(" "));
+    }
+
+    if missing.is_empty() {
+        info!("All packages are already installed, skipping the install");
+        report.record_skipped(
+            Phase::Packages,
+            format!("install {}", installed.join(" ")),
+            resolve_command(packages, system_details).ok(),
+            String::from("every package is already installed"),
+        );
+        None
+    } else {
+        Some(packages.with_only(missing))
+    }
+}
+
+/// Whether `package` is already installed. Packages are treated as missing when
+/// the package manager can't be queried.
+fn is_installed(
+    package_manager: &PackageManager,
+    package: &str,
+    system_details: &SystemDetails,
+    runner: &dyn CommandRunner,
+    report: &RunReport,
+) -> bool {
+    let query = match package_manager.query_operation(package) {
+        Some(query) => query,
+        None => return false,
+    };
+    let resolved = match resolve_command(&query, system_details) {
+        Ok(resolved) => resolved,
+        Err(_) => return false,
+    };
+
+    let started = Instant::now();
+    let output = runner.spawn(&resolved);
+    report.record_command(
+        Phase::Packages,
+        &resolved,
+        started.elapsed(),
+        &output,
+        &Ok(()),
+    );
+
+    match output {
+        Ok(output) => package_manager.query_shows_installed(&output),
+        Err(e) => {
+            debug!(
+                "Unable to query for {}, assuming it is missing: {}",
+                package, e
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::TargetOperatingSystem;
+    use crate::operations::RecordingRunner;
+
+    fn test_config() -> Configuration {
+        let mut config: Configuration = serde_json::from_str(
+            r#"{ "package_list": { "base_packages": ["git", "vim", "tmux"] } }"#,
+        )
+        .unwrap();
+        config.system_details = SystemDetails::new(TargetOperatingSystem::Arch);
+        config
+    }
+
+    fn command_lines(runner: &RecordingRunner) -> Vec<String> {
+        runner.commands().iter().map(|c| c.to_string()).collect()
     }
 
-    run_command(&package_manager.upgrade_operation(), config.system_details)
+    #[test]
+    fn test_only_missing_packages_installed() {
+        let runner = RecordingRunner::new();
+        runner.fail_command("pacman -Q git");
+        runner.fail_command("pacman -Q tmux");
+        let report = RunReport::new();
+        assert!(install_packages(&test_config(), &runner, &report).is_ok());
+        assert_eq!(
+            command_lines(&runner),
+            vec![
+                "pacman -Q git",
+                "pacman -Q vim",
+                "pacman -Q tmux",
+                "sudo pacman -S --noconfirm git tmux",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_all_installed_skips_install() {
+        let runner = RecordingRunner::new();
+        let report = RunReport::new();
+        assert!(install_packages(&test_config(), &runner, &report).is_ok());
+        assert_eq!(runner.commands().len(), 3);
+        assert_eq!(runner.root_requests(), 0);
+        let entries = report.entries();
+        let last = entries.last().unwrap();
+        assert!(last.skipped);
+        assert_eq!(
+            last.command.as_ref().unwrap().to_string(),
+            "sudo pacman -S --noconfirm git vim tmux"
+        );
+    }
+
+    #[test]
+    fn test_custom_package_manager_without_root() {
+        let mut config = test_config();
+        config.update_system = true;
+        config.system_details = config.system_details.with_package_manager(
+            serde_json::from_str(r#"{ "binary": "paru", "needs_root": false }"#).unwrap(),
+        );
+        let runner = RecordingRunner::new();
+        runner.fail_command("pacman -Q vim");
+        assert!(install_packages(&config, &runner, &RunReport::new()).is_ok());
+        let lines: Vec<String> = command_lines(&runner)
+            .into_iter()
+            .filter(|l| !l.starts_with("pacman -Q"))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "paru -Sy --noconfirm",
+                "paru -Syu --noconfirm",
+                "paru -S --noconfirm vim",
+            ]
+        );
+        assert_eq!(runner.root_requests(), 0);
+    }
+
+    #[test]
+    fn test_no_query_installs_everything() {
+        let mut config = test_config();
+        config.system_details = SystemDetails::new(TargetOperatingSystem::Gentoo);
+        let runner = RecordingRunner::new();
+        assert!(install_packages(&config, &runner, &RunReport::new()).is_ok());
+        assert_eq!(
+            command_lines(&runner),
+            vec!["sudo emerge --noreplace git vim tmux"]
+        );
+    }
+
+    #[test]
+    fn test_unknown_system_fails_without_querying() {
+        let mut config = test_config();
+        config.system_details = SystemDetails::new(TargetOperatingSystem::Unknown);
+        let runner = RecordingRunner::new();
+        // Unknown systems can't install anything, but they shouldn't try to query either
+        assert!(install_packages(&config, &runner, &RunReport::new()).is_err());
+        assert!(runner.commands().is_empty());
+    }
 }
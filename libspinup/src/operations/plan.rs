@@ -0,0 +1,378 @@
+//! The plan module walks every phase of a run the same way a real run
+//! would, but only resolves the commands instead of spawning them. This
+//! allows a configuration to be reviewed before it is given `sudo`.
+
+use std::fmt;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::configuration::Configuration;
+use crate::error::{Error, Result};
+use crate::runconfig::RunConfig;
+
+use super::custom_commands::visit_custom_command_operations;
+use super::file_downloads::{
+    display_source, download_description, extract_description, ownership_command, target_file_path,
+};
+use super::packages::visit_package_operations;
+use super::snap::visit_snap_operations;
+use super::{resolve_command, Conditional, Phase, ResolvedCommand, RunnableOperation};
+
+/// A single step that a run would perform
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PlanStep {
+    /// A process that would be spawned
+    Command {
+        phase: Phase,
+        #[serde(flatten)]
+        command: ResolvedCommand,
+    },
+
+    /// A file that would be downloaded
+    Download {
+        phase: Phase,
+        source: String,
+        target: PathBuf,
+    },
+
+    /// A downloaded archive that would be extracted
+    Extract {
+        phase: Phase,
+        archive: PathBuf,
+        dest: PathBuf,
+    },
+
+    /// A command or download that would be skipped, as its `when` condition does not hold
+    Skipped {
+        phase: Phase,
+        operation: String,
+        reason: String,
+    },
+}
+
+/// The ordered set of steps a run would perform
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Plan {
+    pub steps: Vec<PlanStep>,
+}
+
+impl Plan {
+    fn push_command(
+        &mut self,
+        phase: Phase,
+        runnable: &dyn RunnableOperation,
+        config: &Configuration,
+    ) -> Result<()> {
+        let command = resolve_command(runnable, &config.system_details)?;
+        match runnable.skip_reason(&config.system_details) {
+            Some(reason) => self.steps.push(PlanStep::Skipped {
+                phase,
+                operation: command.to_string(),
+                reason,
+            }),
+            None => self.steps.push(PlanStep::Command { phase, command }),
+        }
+        Ok(())
+    }
+
+    /// Render this plan as pretty printed json
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(Error::from)
+    }
+}
+
+impl fmt::Display for PlanStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlanStep::Command { phase, command } => write!(f, "[{}] {}", phase, command),
+            PlanStep::Download {
+                phase,
+                source,
+                target,
+            } => write!(f, "[{}] download {} -> {}", phase, source, target.display()),
+            PlanStep::Extract {
+                phase,
+                archive,
+                dest,
+            } => write!(
+                f,
+                "[{}] extract {} -> {}",
+                phase,
+                archive.display(),
+                dest.display()
+            ),
+            PlanStep::Skipped {
+                phase,
+                operation,
+                reason,
+            } => write!(f, "[{}] skip {} ({})", phase, operation, reason),
+        }
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "{}", step)?;
+        }
+        Ok(())
+    }
+}
+
+/// Build the [`Plan`](struct.Plan.html) of everything that running `config`
+/// with `run_config` would do, in the order it would be done.
+///
+/// Nothing is spawned or downloaded while building the plan.
+pub fn build_plan(config: &Configuration, run_config: &RunConfig) -> Result<Plan> {
+    let mut plan = Plan::default();
+
+    if run_config.run_package_installs {
+        visit_package_operations(config, config.package_list.as_ref(), &mut |phase, op| {
+            plan.push_command(phase, op, config)
+        })?;
+    }
+
+    if run_config.run_file_downloads {
+        plan_download_operations(config, &mut plan)?;
+    }
+
+    if run_config.run_custom_commands {
+        visit_custom_command_operations(config, &mut |phase, op| {
+            plan.push_command(phase, op, config)
+        })?;
+    }
+
+    if run_config.run_snap_installs {
+        visit_snap_operations(config, &mut |op| {
+            plan.push_command(Phase::Snaps, op, config)
+        })?;
+    }
+
+    Ok(plan)
+}
+
+fn plan_download_operations(config: &Configuration, plan: &mut Plan) -> Result<()> {
+    if let Some(operations) = &config.file_downloads {
+        for operation in operations {
+            let target = operation
+                .download_target_base()
+                .ok_or_else(|| Error::from("Unable to resolve target directory"))?;
+            let when = operation.when.as_ref();
+            let skip_reason = when.and_then(|when| when.unmet(&config.system_details));
+
+            for file in &operation.files {
+                let file_path = target_file_path(file, &target);
+                plan.steps.push(match &skip_reason {
+                    Some(reason) => PlanStep::Skipped {
+                        phase: Phase::FileDownloads,
+                        operation: download_description(file, &file_path),
+                        reason: reason.clone(),
+                    },
+                    None => PlanStep::Download {
+                        phase: Phase::FileDownloads,
+                        source: display_source(file),
+                        target: file_path.clone(),
+                    },
+                });
+
+                if let Some(chown) = ownership_command(file, &file_path) {
+                    plan.push_command(
+                        Phase::FileDownloads,
+                        &Conditional::new(&chown, when),
+                        config,
+                    )?;
+                }
+
+                if let Some(extract) = &file.extract {
+                    let dest = extract.destination(&target);
+                    plan.steps.push(match &skip_reason {
+                        Some(reason) => PlanStep::Skipped {
+                            phase: Phase::FileDownloads,
+                            operation: extract_description(&file_path, &dest),
+                            reason: reason.clone(),
+                        },
+                        None => PlanStep::Extract {
+                            phase: Phase::FileDownloads,
+                            archive: file_path,
+                            dest,
+                        },
+                    });
+                }
+            }
+
+            if let Some(after) = &operation.after_complete {
+                let after = Conditional::new(after, when);
+                plan.push_command(Phase::FileDownloads, &after, config)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::{
+        Condition, CustomCommand, FileDownloadDefinition, FileDownloadOperation, PackageList,
+        SnapChannel, SnapPackage, Snaps, StandardSnaps, SystemDetails, TargetOperatingSystem,
+    };
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn test_config() -> Configuration {
+        Configuration {
+            include: None,
+            vars: None,
+            update_system: true,
+            package_list: Some(PackageList {
+                base_packages: Some(strings(&["git", "vim"])),
+                distro_packages: None,
+                continue_on_error: false,
+            }),
+            file_downloads: Some(vec![FileDownloadOperation {
+                base_dir: Some(String::from("/tmp/fonts")),
+                after_complete: Some(CustomCommand::new(
+                    String::from("fc-cache"),
+                    Some(strings(&["-f"])),
+                    false,
+                )),
+                files: vec![FileDownloadDefinition {
+                    source: String::from("https://example.com/font.ttf"),
+                    github_release: None,
+                    target: String::from("font.ttf"),
+                    sha256: None,
+                    sha512: None,
+                    extract: None,
+                    mode: None,
+                    owner: None,
+                    group: None,
+                    headers: None,
+                    auth: None,
+                    config_dir: None,
+                }],
+                continue_on_error: false,
+                when: None,
+                headers: None,
+                auth: None,
+                settings: Default::default(),
+            }]),
+            download_settings: None,
+            snaps: Some(Snaps {
+                standard_snaps: StandardSnaps {
+                    names: strings(&["spotify"]),
+                    continue_on_error: false,
+                },
+                alternate_snaps: Some(vec![SnapPackage {
+                    name: String::from("code"),
+                    classic: true,
+                    channel: SnapChannel::Stable,
+                    continue_on_error: false,
+                    when: None,
+                }]),
+                when: None,
+            }),
+            custom_commands: Some(vec![CustomCommand::new(
+                String::from("systemctl"),
+                Some(strings(&["enable", "tlp"])),
+                true,
+            )]),
+            command_sets: None,
+            continue_on_error: false,
+            package_manager: None,
+            system_details: SystemDetails::new(TargetOperatingSystem::Arch),
+        }
+    }
+
+    fn all_phases() -> RunConfig {
+        RunConfig::new(String::new(), "off", true, true, true, true, false)
+    }
+
+    #[test]
+    fn test_plan_contains_every_phase_in_order() {
+        let plan = build_plan(&test_config(), &all_phases()).unwrap();
+        let lines: Vec<String> = plan.steps.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "[system_upgrade] sudo pacman -Sy --noconfirm",
+                "[system_upgrade] sudo pacman -Syu --noconfirm",
+                "[packages] sudo pacman -S --noconfirm git vim",
+                "[file_downloads] download https://example.com/font.ttf -> /tmp/fonts/font.ttf",
+                "[file_downloads] fc-cache -f",
+                "[custom_commands] sudo systemctl enable tlp",
+                "[snaps] sudo snap install spotify",
+                "[snaps] sudo snap install code --stable --classic",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_skips_disabled_phases() {
+        let run_config = RunConfig::new(String::new(), "off", false, false, true, true, false);
+        let plan = build_plan(&test_config(), &run_config).unwrap();
+        assert_eq!(plan.steps.len(), 3);
+        assert!(plan.steps.iter().all(|s| match s {
+            PlanStep::Command { phase, .. } => {
+                *phase == Phase::CustomCommands || *phase == Phase::Snaps
+            }
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_plan_json_output() {
+        let run_config = RunConfig::new(String::new(), "off", false, false, false, true, false);
+        let plan = build_plan(&test_config(), &run_config).unwrap();
+        let actual: serde_json::Value = serde_json::from_str(&plan.to_json().unwrap()).unwrap();
+        assert_eq!(
+            actual,
+            serde_json::json!({
+                "steps": [{
+                    "type": "command",
+                    "phase": "custom_commands",
+                    "command": "sudo",
+                    "args": ["systemctl", "enable", "tlp"]
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_plan_shows_skipped_steps() {
+        let debian_only = Condition {
+            os: Some(String::from("debian")),
+            ..Condition::default()
+        };
+        let mut config = test_config();
+        config.file_downloads.as_mut().unwrap()[0].when = Some(debian_only.clone());
+        config.snaps.as_mut().unwrap().when = Some(debian_only.clone());
+        config.custom_commands.as_mut().unwrap()[0].when = Some(debian_only);
+        let run_config = RunConfig::new(String::new(), "off", false, true, true, true, false);
+
+        let plan = build_plan(&config, &run_config).unwrap();
+        let lines: Vec<String> = plan.steps.iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "[file_downloads] skip download https://example.com/font.ttf -> /tmp/fonts/font.ttf (the os is not debian)",
+                "[file_downloads] skip fc-cache -f (the os is not debian)",
+                "[custom_commands] skip sudo systemctl enable tlp (the os is not debian)",
+                "[snaps] skip sudo snap install spotify (the os is not debian)",
+                "[snaps] skip sudo snap install code --stable --classic (the os is not debian)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_propagates_resolution_errors() {
+        let mut config = test_config();
+        config.custom_commands = Some(vec![CustomCommand::new(String::new(), None, false)]);
+        let run_config = RunConfig::new(String::new(), "off", false, false, false, true, false);
+        assert!(build_plan(&config, &run_config).is_err());
+    }
+}
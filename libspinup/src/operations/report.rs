@@ -0,0 +1,266 @@
+//! The report module collects the outcome of every operation performed
+//! during a run, so that it can be written out as a machine readable
+//! document once the run finishes.
+
+use std::fs::File;
+use std::io::Write;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+
+use super::{CommandOutput, Phase, ResolvedCommand};
+
+/// The recorded outcome of a single operation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReportEntry {
+    /// The phase the operation ran in
+    pub phase: Phase,
+
+    /// A human readable description of the operation, for commands this
+    /// is the full command line
+    pub operation: String,
+
+    /// The resolved command, if this operation spawned one
+    pub command: Option<ResolvedCommand>,
+
+    /// The exit code of the command, if one was spawned and exited normally
+    pub exit_code: Option<i32>,
+
+    /// How long the operation took, in milliseconds
+    pub duration_ms: u64,
+
+    /// Everything the command wrote to stdout
+    pub stdout: String,
+
+    /// Everything the command wrote to stderr
+    pub stderr: String,
+
+    /// Whether the operation was skipped instead of being run
+    pub skipped: bool,
+
+    /// Why the operation was skipped
+    pub skip_reason: Option<String>,
+
+    /// The error the operation failed with, if any
+    pub error: Option<String>,
+}
+
+impl ReportEntry {
+    fn new(phase: Phase, operation: String) -> Self {
+        ReportEntry {
+            phase,
+            operation,
+            command: None,
+            exit_code: None,
+            duration_ms: 0,
+            stdout: String::new(),
+            stderr: String::new(),
+            skipped: false,
+            skip_reason: None,
+            error: None,
+        }
+    }
+
+    /// Whether this operation failed
+    pub fn failed(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+/// The full report for a run, as written to disk
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReportSummary {
+    /// Whether the run completed without errors
+    pub success: bool,
+
+    /// The error that ended the run, if any
+    pub error: Option<String>,
+
+    /// Every operation, in the order they finished
+    pub operations: Vec<ReportEntry>,
+}
+
+/// Collects a [`ReportEntry`](struct.ReportEntry.html) for each operation as a run progresses.
+///
+/// Entries are added through a shared reference so a single report can be
+/// used by operations that run concurrently, such as file downloads.
+#[derive(Debug, Default)]
+pub struct RunReport {
+    entries: Mutex<Vec<ReportEntry>>,
+}
+
+impl RunReport {
+    /// Create a new, empty report
+    pub fn new() -> Self {
+        RunReport::default()
+    }
+
+    /// Record a command that was run, along with what it output and
+    /// the final `result` of running it
+    pub(crate) fn record_command(
+        &self,
+        phase: Phase,
+        command: &ResolvedCommand,
+        elapsed: Duration,
+        output: &Result<CommandOutput>,
+        result: &Result<()>,
+    ) {
+        let mut entry = ReportEntry::new(phase, command.to_string());
+        entry.command = Some(command.clone());
+        entry.duration_ms = elapsed.as_millis() as u64;
+        match output {
+            Ok(output) => {
+                entry.exit_code = output.code;
+                entry.stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+                entry.stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+                entry.error = result.as_ref().err().map(|e| e.to_string());
+            }
+            Err(e) => entry.error = Some(e.to_string()),
+        }
+        self.entries().push(entry);
+    }
+
+    /// Record an operation that did not spawn a command, such as a download
+    pub(crate) fn record_operation<T>(
+        &self,
+        phase: Phase,
+        operation: String,
+        elapsed: Duration,
+        result: &Result<T>,
+    ) {
+        let mut entry = ReportEntry::new(phase, operation);
+        entry.duration_ms = elapsed.as_millis() as u64;
+        entry.error = result.as_ref().err().map(|e| e.to_string());
+        self.entries().push(entry);
+    }
+
+    /// Record an operation that was skipped instead of being run
+    pub(crate) fn record_skipped(
+        &self,
+        phase: Phase,
+        operation: String,
+        command: Option<ResolvedCommand>,
+        reason: String,
+    ) {
+        let mut entry = ReportEntry::new(phase, operation);
+        entry.command = command;
+        entry.skipped = true;
+        entry.skip_reason = Some(reason);
+        self.entries().push(entry);
+    }
+
+    /// The entries recorded so far, in the order they finished
+    pub fn entries(&self) -> MutexGuard<'_, Vec<ReportEntry>> {
+        // A poisoned lock only means another recording panicked, the data is still usable
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Build the summary of this report for a run that ended with `result`
+    pub fn summary(&self, result: &Result<()>) -> ReportSummary {
+        ReportSummary {
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            operations: self.entries().clone(),
+        }
+    }
+
+    /// Write this report as json to the file at `path`
+    pub fn write_to(&self, path: &str, result: &Result<()>) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.summary(result))?;
+        let mut file = File::create(path)?;
+        file.write_all(json.as_bytes()).map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command() -> ResolvedCommand {
+        ResolvedCommand::new("sudo", &[String::from("snap"), String::from("install")])
+    }
+
+    #[test]
+    fn test_record_command_output() {
+        let report = RunReport::new();
+        let output = CommandOutput {
+            code: Some(0),
+            stdout: b"done".to_vec(),
+            stderr: Vec::new(),
+        };
+        report.record_command(
+            Phase::Snaps,
+            &command(),
+            Duration::from_millis(12),
+            &Ok(output),
+            &Ok(()),
+        );
+
+        let entries = report.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].operation, "sudo snap install");
+        assert_eq!(entries[0].exit_code, Some(0));
+        assert_eq!(entries[0].duration_ms, 12);
+        assert_eq!(entries[0].stdout, "done");
+        assert!(!entries[0].failed());
+    }
+
+    #[test]
+    fn test_record_command_failed_status() {
+        let report = RunReport::new();
+        let output = CommandOutput {
+            code: Some(2),
+            stdout: Vec::new(),
+            stderr: b"no such snap".to_vec(),
+        };
+        report.record_command(
+            Phase::Snaps,
+            &command(),
+            Duration::default(),
+            &Ok(output),
+            &Err(Error::from("bad status")),
+        );
+
+        let entries = report.entries();
+        assert_eq!(entries[0].exit_code, Some(2));
+        assert_eq!(entries[0].stderr, "no such snap");
+        assert_eq!(entries[0].error, Some(String::from("bad status")));
+    }
+
+    #[test]
+    fn test_summary_json() {
+        let report = RunReport::new();
+        report.record_operation(
+            Phase::FileDownloads,
+            String::from("download a -> b"),
+            Duration::from_millis(5),
+            &Ok(()),
+        );
+        let summary = report.summary(&Err(Error::from("failed")));
+        let actual = serde_json::to_value(&summary).unwrap();
+        assert_eq!(
+            actual,
+            serde_json::json!({
+                "success": false,
+                "error": "failed",
+                "operations": [{
+                    "phase": "file_downloads",
+                    "operation": "download a -> b",
+                    "command": null,
+                    "exit_code": null,
+                    "duration_ms": 5,
+                    "stdout": "",
+                    "stderr": "",
+                    "skipped": false,
+                    "skip_reason": null,
+                    "error": null
+                }]
+            })
+        );
+    }
+}
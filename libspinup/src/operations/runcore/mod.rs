@@ -1,10 +1,111 @@
+//! The runcore module contains the pieces that actually spawn processes.
+//!
+//! All commands are executed through a [`CommandRunner`](trait.CommandRunner.html),
+//! which allows the process spawning to be swapped out, for example with the
+//! [`RecordingRunner`](struct.RecordingRunner.html) when embedding or testing.
+
+use crate::error::{Error, Result};
+use crate::operations::ResolvedCommand;
+
+mod output;
+mod recording;
 mod standard;
-mod testing;
 
-cfg_if::cfg_if! {
-    if #[cfg(test)] {
-        pub use testing::{internal_runner, get_root, passed_command, passed_args, reset, called_root};
+pub use recording::RecordingRunner;
+pub use standard::ProcessRunner;
+
+/// The `CommandRunner` trait is responsible for executing fully resolved
+/// commands on behalf of [`RunnableOperation`](../trait.RunnableOperation.html)
+/// instances.
+///
+/// A runner is shared by everything the run does, including the commands that run on other
+/// threads while files download, so it must be `Send + Sync`. This also lets the future of
+/// [`run_app_with_runner`](../fn.run_app_with_runner.html) be spawned.
+pub trait CommandRunner: Send + Sync {
+    /// Run `command` with its arguments, working directory and environment, waiting for it to complete.
+    ///
+    /// This should only return an error if the command could not be run at all, or was stopped
+    /// after running for longer than its `timeout` with [`Error::Timeout`](../error/enum.Error.html#variant.Timeout).
+    /// An unsuccessful exit is reported through the returned [`CommandOutput`](struct.CommandOutput.html).
+    fn spawn(&self, command: &ResolvedCommand) -> Result<CommandOutput>;
+
+    /// Obtain root permissions for subsequent `sudo` commands.
+    ///
+    /// This is called before any command that needs root is spawned.
+    fn acquire_root(&self) -> Result<()>;
+}
+
+/// The result of a command that was run by a [`CommandRunner`](trait.CommandRunner.html)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandOutput {
+    /// The exit code of the command, `None` if it was terminated by a signal
+    pub code: Option<i32>,
+
+    /// Everything the command wrote to stdout
+    pub stdout: Vec<u8>,
+
+    /// Everything the command wrote to stderr
+    pub stderr: Vec<u8>,
+}
+
+impl CommandOutput {
+    /// Output for a command that exited successfully without writing anything
+    pub fn success() -> Self {
+        CommandOutput {
+            code: Some(0),
+            ..CommandOutput::default()
+        }
+    }
+}
+
+/// Helper that handles the process output of any run commands and offers logging capabilities.
+pub(crate) fn handle_process_output(cmd: &str, output: &CommandOutput) -> Result<()> {
+    if let Some(code) = output.code {
+        if code == 0 {
+            info!("Command execution of {} completed successfully", cmd);
+            Ok(())
+        } else {
+            use log::{max_level, LevelFilter};
+            warn!("Command execution of {} returned status of {}", cmd, code);
+
+            // Don't bother building trace output unless we're actually using it
+            if max_level() == LevelFilter::Trace {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                if !stdout.is_empty() {
+                    debug!("Stdout: \n{}", stdout);
+                }
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                if !stderr.is_empty() {
+                    debug!("Stderr: \n{}", stderr);
+                }
+            }
+            Err(Error::from(format!(
+                "`{}` returned status of '{}'.\nRun with higher verbosity to see more output",
+                cmd, code
+            )))
+        }
     } else {
-        pub use standard::{internal_runner, get_root};
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_output_ok_none_status() {
+        let actual = handle_process_output("test", &CommandOutput::default());
+        assert!(actual.is_ok());
+    }
+
+    #[test]
+    fn test_process_output_err_non_zero_status() {
+        let output = CommandOutput {
+            code: Some(1),
+            ..CommandOutput::default()
+        };
+        let actual = handle_process_output("test", &output);
+        assert!(actual.is_err());
     }
 }
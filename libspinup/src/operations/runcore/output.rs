@@ -0,0 +1,143 @@
+//! The output module shows what a command writes while it is still running,
+//! with each line prefixed by the phase and command it came from.
+
+use std::env;
+use std::io::{self, Write};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::runconfig::OutputMode;
+
+/// Which of its output streams a command wrote a line to
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Pipe {
+    Stdout,
+    Stderr,
+}
+
+const CYAN: &str = "\x1b[36m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+lazy_static! {
+    static ref COLOR_STDOUT: bool = use_color(libc::STDOUT_FILENO);
+    static ref COLOR_STDERR: bool = use_color(libc::STDERR_FILENO);
+}
+
+/// Whether lines written to `fd` should be colored, which is only done for a terminal
+/// and can be turned off by setting `NO_COLOR`
+fn use_color(fd: libc::c_int) -> bool {
+    // Safe, as `isatty` only looks at the descriptor
+    env::var_os("NO_COLOR").is_none() && unsafe { libc::isatty(fd) } == 1
+}
+
+/// Shows the lines written by a single command as set by its [`OutputMode`](../../enum.OutputMode.html).
+///
+/// Only commands with a label are shown, and with `OnFailure` the lines are held
+/// back until the command is known to have failed.
+#[derive(Debug)]
+pub(crate) struct LiveOutput {
+    mode: OutputMode,
+    label: Option<String>,
+    held: Mutex<Vec<(Pipe, String)>>,
+}
+
+impl LiveOutput {
+    pub(crate) fn new(mode: OutputMode, label: Option<String>) -> Self {
+        LiveOutput {
+            mode,
+            label,
+            held: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Handle the next `line` the command wrote to `pipe`, without its line ending
+    pub(crate) fn line(&self, pipe: Pipe, line: &str) {
+        match (&self.label, self.mode) {
+            (Some(label), OutputMode::Live) => show(label, pipe, line),
+            (Some(_), OutputMode::OnFailure) => self.held().push((pipe, line.to_owned())),
+            _ => {}
+        }
+    }
+
+    /// Show the lines that were held back, as the command failed
+    pub(crate) fn failed(&self) {
+        if let Some(label) = &self.label {
+            for (pipe, line) in self.held().drain(..) {
+                show(label, pipe, &line);
+            }
+        }
+    }
+
+    fn held(&self) -> MutexGuard<'_, Vec<(Pipe, String)>> {
+        // A poisoned lock only means a reader panicked, the lines are still usable
+        self.held
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn show(label: &str, pipe: Pipe, line: &str) {
+    // There is nowhere left to report a failure to write the output
+    let _ = match pipe {
+        Pipe::Stdout => {
+            let color = Some(CYAN).filter(|_| *COLOR_STDOUT);
+            io::stdout()
+                .lock()
+                .write_all(format_line(label, line, color).as_bytes())
+        }
+        Pipe::Stderr => {
+            let color = Some(YELLOW).filter(|_| *COLOR_STDERR);
+            io::stderr()
+                .lock()
+                .write_all(format_line(label, line, color).as_bytes())
+        }
+    };
+}
+
+/// A line of output as it is shown, with the label in `color` when one is given
+fn format_line(label: &str, line: &str, color: Option<&str>) -> String {
+    match color {
+        Some(color) => format!("{}{}{} | {}\n", color, label, RESET, line),
+        None => format!("{} | {}\n", label, line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_line() {
+        assert_eq!(
+            format_line("[packages] apt-get", "Reading package lists...", None),
+            "[packages] apt-get | Reading package lists...\n"
+        );
+        assert_eq!(
+            format_line("[snaps] snap", "done", Some(CYAN)),
+            "\x1b[36m[snaps] snap\x1b[0m | done\n"
+        );
+    }
+
+    #[test]
+    fn test_lines_are_only_held_on_failure_mode() {
+        let on_failure = LiveOutput::new(OutputMode::OnFailure, Some(String::from("[x] y")));
+        on_failure.line(Pipe::Stdout, "one");
+        on_failure.line(Pipe::Stderr, "two");
+        assert_eq!(
+            *on_failure.held(),
+            vec![
+                (Pipe::Stdout, String::from("one")),
+                (Pipe::Stderr, String::from("two"))
+            ]
+        );
+        on_failure.failed();
+        assert!(on_failure.held().is_empty());
+
+        let unlabelled = LiveOutput::new(OutputMode::OnFailure, None);
+        unlabelled.line(Pipe::Stdout, "probe");
+        assert!(unlabelled.held().is_empty());
+        let hidden = LiveOutput::new(OutputMode::Hidden, Some(String::from("[x] y")));
+        hidden.line(Pipe::Stdout, "quiet");
+        assert!(hidden.held().is_empty());
+    }
+}
@@ -0,0 +1,191 @@
+//! A [`CommandRunner`](trait.CommandRunner.html) that never spawns anything,
+//! and instead records every call made to it. This is useful when embedding
+//! `libspinup` and asserting against the commands a run would execute.
+//!
+//! Each runner keeps its own records, so separate instances can be used
+//! from separate threads without interfering with one another.
+
+use std::sync::{Mutex, MutexGuard};
+
+use crate::error::Result;
+use crate::operations::ResolvedCommand;
+
+use super::{CommandOutput, CommandRunner};
+
+/// Records commands instead of running them
+#[derive(Debug, Default)]
+pub struct RecordingRunner {
+    records: Mutex<Records>,
+}
+
+#[derive(Debug, Default)]
+struct Records {
+    commands: Vec<ResolvedCommand>,
+    root_requests: usize,
+    failing: Vec<String>,
+}
+
+impl RecordingRunner {
+    /// Create a new `RecordingRunner` with no recorded calls
+    pub fn new() -> Self {
+        RecordingRunner::default()
+    }
+
+    /// Every command that was passed to [`spawn`](trait.CommandRunner.html#tymethod.spawn),
+    /// in the order they were received
+    pub fn commands(&self) -> Vec<ResolvedCommand> {
+        self.records().commands.clone()
+    }
+
+    /// The most recently spawned command, if any
+    pub fn last_command(&self) -> Option<ResolvedCommand> {
+        self.records().commands.last().cloned()
+    }
+
+    /// The number of times root permissions were requested
+    pub fn root_requests(&self) -> usize {
+        self.records().root_requests
+    }
+
+    /// Make every later command that starts with `command` exit with a status of `1`.
+    /// This matches on whole words, and ignores a leading `sudo`, so `"pacman -Q"` will
+    /// match `pacman -Q git` but not `sudo pacman -S git`.
+    pub fn fail_command(&self, command: &str) {
+        self.records().failing.push(command.to_string());
+    }
+
+    /// Clear all recorded calls and failing commands
+    pub fn reset(&self) {
+        *self.records() = Records::default();
+    }
+
+    fn records(&self) -> MutexGuard<'_, Records> {
+        // A poisoned lock only means another recording panicked, the data is still usable
+        self.records
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl CommandRunner for RecordingRunner {
+    fn spawn(&self, command: &ResolvedCommand) -> Result<CommandOutput> {
+        let mut records = self.records();
+        records.commands.push(command.clone());
+
+        let mut words: Vec<&str> = vec![&command.command];
+        words.extend(command.args.iter().map(|a| &a[..]));
+        if command.command == "sudo" {
+            words.remove(0);
+        }
+        let fails = records.failing.iter().any(|f| {
+            let prefix: Vec<&str> = f.split_whitespace().collect();
+            words.starts_with(&prefix)
+        });
+        if fails {
+            Ok(CommandOutput {
+                code: Some(1),
+                ..CommandOutput::default()
+            })
+        } else {
+            Ok(CommandOutput::success())
+        }
+    }
+
+    fn acquire_root(&self) -> Result<()> {
+        self.records().root_requests += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_records_in_order() {
+        let runner = RecordingRunner::new();
+        runner.spawn(&ResolvedCommand::new("first", &[])).unwrap();
+        runner
+            .spawn(&ResolvedCommand::new("second", &[String::from("arg")]))
+            .unwrap();
+        let commands = runner.commands();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].command, "first");
+        assert_eq!(commands[1].args, vec![String::from("arg")]);
+    }
+
+    #[test]
+    fn test_fail_command_direct_and_sudo() {
+        let runner = RecordingRunner::new();
+        runner.fail_command("snap");
+        let direct = runner.spawn(&ResolvedCommand::new("snap", &[])).unwrap();
+        let sudo = runner
+            .spawn(&ResolvedCommand::new(
+                "sudo",
+                &[String::from("snap"), String::from("install")],
+            ))
+            .unwrap();
+        let other = runner.spawn(&ResolvedCommand::new("ls", &[])).unwrap();
+        assert_eq!(direct.code, Some(1));
+        assert_eq!(sudo.code, Some(1));
+        assert_eq!(other.code, Some(0));
+    }
+
+    #[test]
+    fn test_fail_command_matches_whole_words() {
+        let runner = RecordingRunner::new();
+        runner.fail_command("pacman -Q");
+        let query = runner
+            .spawn(&ResolvedCommand::new(
+                "pacman",
+                &[String::from("-Q"), String::from("git")],
+            ))
+            .unwrap();
+        let install = runner
+            .spawn(&ResolvedCommand::new(
+                "sudo",
+                &[String::from("pacman"), String::from("-S")],
+            ))
+            .unwrap();
+        let partial = runner
+            .spawn(&ResolvedCommand::new("pacman", &[String::from("-Qi")]))
+            .unwrap();
+        assert_eq!(query.code, Some(1));
+        assert_eq!(install.code, Some(0));
+        assert_eq!(partial.code, Some(0));
+    }
+
+    #[test]
+    fn test_reset_clears_records() {
+        let runner = RecordingRunner::new();
+        runner.acquire_root().unwrap();
+        runner.spawn(&ResolvedCommand::new("first", &[])).unwrap();
+        runner.reset();
+        assert!(runner.commands().is_empty());
+        assert_eq!(runner.root_requests(), 0);
+    }
+
+    #[test]
+    fn test_runners_are_independent_across_threads() {
+        let handles: Vec<_> = (0..4)
+            .map(|n| {
+                thread::spawn(move || {
+                    let runner = RecordingRunner::new();
+                    for _ in 0..=n {
+                        runner
+                            .spawn(&ResolvedCommand::new(&n.to_string(), &[]))
+                            .unwrap();
+                    }
+                    runner.commands()
+                })
+            })
+            .collect();
+
+        for (n, handle) in handles.into_iter().enumerate() {
+            let commands = handle.join().unwrap();
+            assert_eq!(commands.len(), n + 1);
+            assert!(commands.iter().all(|c| c.command == n.to_string()));
+        }
+    }
+}
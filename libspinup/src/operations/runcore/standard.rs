@@ -1,70 +1,205 @@
-use std::process::{Command, Stdio};
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use crate::error::{Error, Result};
+use crate::operations::ResolvedCommand;
+use crate::runconfig::OutputMode;
 
-#[allow(dead_code)]
-pub fn internal_runner(command: &str, args: &[String]) -> Result<()> {
-    let status = Command::new(command)
-        .args(args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .args(args)
-        .spawn()?
-        .wait_with_output()?;
-
-    handle_process_output(
-        command,
-        status.status.code(),
-        &status.stdout,
-        &status.stderr,
-    )
-}
+use super::output::{LiveOutput, Pipe};
+use super::{CommandOutput, CommandRunner};
 
-/// Helper that will run `sudo -v` to obtain a prompt to enter a user's password.
-/// As a session with sudo lasts ~15 minutes, the user's authentication for this should
-/// serve for the entire time this application runs. Subsequent calls will not require
-/// password entry if we're still within the time limit.
-#[allow(dead_code)]
-pub fn get_root() -> Result<()> {
-    let exit_status = Command::new("sudo").arg("-v").spawn()?.wait()?;
+/// The default [`CommandRunner`](trait.CommandRunner.html), which spawns each
+/// command as a child process.
+///
+/// The output of each command is always captured, and is also shown while it runs
+/// as set by its [`OutputMode`](../enum.OutputMode.html), which by default shows each line as it is written.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ProcessRunner {
+    output: OutputMode,
+}
 
-    if exit_status.success() {
-        Ok(())
-    } else {
-        Err("Unable to authenticate for sudo".into())
+impl ProcessRunner {
+    /// Create a runner that shows the output of the commands it runs as set by `output`
+    pub fn new(output: OutputMode) -> Self {
+        ProcessRunner { output }
     }
 }
 
-/// Helper that handles the process output of any run commands and offers logging capabilities.
-fn handle_process_output(
-    cmd: &str,
-    status: Option<i32>,
-    stdout: &[u8],
-    stderr: &[u8],
-) -> Result<()> {
-    if let Some(code) = status {
-        if code == 0 {
-            info!("Command execution of {} completed successfully", cmd);
+impl CommandRunner for ProcessRunner {
+    fn spawn(&self, command: &ResolvedCommand) -> Result<CommandOutput> {
+        let mut process = Command::new(&command.command);
+        process.args(&command.args);
+        if let Some(cwd) = &command.cwd {
+            if !cwd.is_dir() {
+                return Err(Error::from(format!(
+                    "Cannot run {}, {} is not a directory",
+                    command.command,
+                    cwd.display()
+                )));
+            }
+            process.current_dir(cwd);
+        }
+        if command.clear_env {
+            process.env_clear();
+        }
+        process
+            .envs(&command.env)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        if command.timeout.is_some() {
+            // Leading a process group of its own lets everything the command starts be signalled at once
+            process.process_group(0);
+        }
+
+        let mut child = process.spawn()?;
+        let live = Arc::new(LiveOutput::new(self.output, command.label.clone()));
+        let stdout = read_in_background(child.stdout.take(), Pipe::Stdout, live.clone());
+        let stderr = read_in_background(child.stderr.take(), Pipe::Stderr, live.clone());
+
+        let status = match command.timeout {
+            Some(timeout) => match wait_until(&mut child, Instant::now() + timeout)? {
+                Some(status) => status,
+                None => {
+                    warn!(
+                        "`{}` is still running after {:?}, stopping it",
+                        command, timeout
+                    );
+                    stop_group(&mut child, command)?;
+                    // Anything that escaped the group may still hold the pipes, so the readers are only given so long
+                    finish_reading(vec![stdout, stderr], Instant::now() + STOP_GRACE);
+                    live.failed();
+                    return Err(Error::Timeout {
+                        command: command.to_string(),
+                        timeout,
+                    });
+                }
+            },
+            None => child.wait()?,
+        };
+
+        let output = CommandOutput {
+            code: status.code(),
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        };
+        if !status.success() {
+            live.failed();
+        }
+        Ok(output)
+    }
+
+    /// Runs `sudo -v` to obtain a prompt to enter a user's password.
+    /// As a session with sudo lasts ~15 minutes, the user's authentication for this should
+    /// serve for the entire time this application runs. Subsequent calls will not require
+    /// password entry if we're still within the time limit.
+    fn acquire_root(&self) -> Result<()> {
+        let exit_status = Command::new("sudo").arg("-v").spawn()?.wait()?;
+
+        if exit_status.success() {
             Ok(())
         } else {
-            use log::{max_level, LevelFilter};
-            warn!("Command execution of {} returned status of {}", cmd, code);
-
-            // Don't bother building trace output unless we're actually using it
-            if max_level() == LevelFilter::Trace {
-                let stdout = String::from_utf8_lossy(stdout);
-                if stdout.len() > 0 {
-                    debug!("Stdout: \n{}", stdout);
-                }
-                let stderr = String::from_utf8_lossy(stderr);
-                if stderr.len() > 0 {
-                    debug!("Stderr: \n{}", stderr);
+            Err("Unable to authenticate for sudo".into())
+        }
+    }
+}
+
+/// How often a command with a timeout is checked on
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long a command that timed out is given to exit once asked to, before it is killed
+const STOP_GRACE: Duration = Duration::from_secs(2);
+
+/// Read all of `pipe` on another thread, so the command never blocks on a full pipe,
+/// passing each line to `live` as it arrives
+fn read_in_background<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    which: Pipe,
+    live: Arc<LiveOutput>,
+) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(pipe) = pipe {
+            let mut reader = BufReader::new(pipe);
+            let mut line = Vec::new();
+            while let Ok(read) = reader.read_until(b'\n', &mut line) {
+                if read == 0 {
+                    break;
                 }
+                let text = String::from_utf8_lossy(&line);
+                live.line(which, text.trim_end_matches(&['\r', '\n'][..]));
+                buffer.append(&mut line);
             }
-            Err(Error::from(format!("Package manager returned status of '{}'.\nRun with higher verbosity to see more output", code)))
         }
-    } else {
+        buffer
+    })
+}
+
+/// Wait until `readers` reach the end of their pipes, or `deadline` passes, so that the
+/// lines they pass on are all seen before the command is reported as failed
+fn finish_reading(readers: Vec<JoinHandle<Vec<u8>>>, deadline: Instant) {
+    while readers.iter().any(|reader| !reader.is_finished()) && Instant::now() < deadline {
+        thread::sleep(POLL_INTERVAL);
+    }
+    for reader in readers {
+        if reader.is_finished() {
+            let _ = reader.join();
+        }
+    }
+}
+
+/// Wait for `child` to exit, returning `None` if it is still running at `deadline`
+fn wait_until(child: &mut Child, deadline: Instant) -> Result<Option<ExitStatus>> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Stop the process group led by `child`.
+///
+/// The group is asked to terminate first, as `sudo` passes that on to the command it started,
+/// then anything left is killed. Processes that run as root can only be killed through `sudo`.
+fn stop_group(child: &mut Child, command: &ResolvedCommand) -> Result<()> {
+    let group = -(child.id() as libc::pid_t);
+    let _ = signal(group, libc::SIGTERM);
+    if wait_until(child, Instant::now() + STOP_GRACE)?.is_some() {
+        // Anything the command left running in the group goes with it
+        let _ = signal(group, libc::SIGKILL);
+        return Ok(());
+    }
+
+    if let Err(e) = signal(group, libc::SIGKILL) {
+        if e.raw_os_error() == Some(libc::EPERM) && command.command == "sudo" {
+            Command::new("sudo")
+                .args(["-n", "kill", "-KILL", "--", &group.to_string()])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()?;
+        }
+    }
+    if wait_until(child, Instant::now() + STOP_GRACE)?.is_none() {
+        warn!("Unable to stop `{}`, leaving it running", command);
+    }
+    Ok(())
+}
+
+/// Send `signal` to the process, or the process group when `pid` is negative
+fn signal(pid: libc::pid_t, signal: libc::c_int) -> io::Result<()> {
+    // Safe, as `kill` only reads its arguments
+    if unsafe { libc::kill(pid, signal) } == 0 {
         Ok(())
+    } else {
+        Err(io::Error::last_os_error())
     }
 }
 
@@ -73,8 +208,77 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_process_output_ok_none_status() {
-        let actual = handle_process_output("test", None, &[], &[]);
-        assert!(actual.is_ok());
+    fn test_process_runner_captures_output() {
+        assert_eq!(ProcessRunner::default().output, OutputMode::Live);
+        let actual = ProcessRunner::new(OutputMode::Live)
+            .spawn(&ResolvedCommand::new("echo", &[String::from("hello")]))
+            .unwrap();
+        assert_eq!(actual.code, Some(0));
+        assert_eq!(actual.stdout, b"hello\n".to_vec());
+    }
+
+    #[test]
+    fn test_process_runner_reports_failed_status() {
+        let actual = ProcessRunner::new(OutputMode::Live)
+            .spawn(&ResolvedCommand::new("false", &[]))
+            .unwrap();
+        assert_eq!(actual.code, Some(1));
+    }
+
+    #[test]
+    fn test_process_runner_missing_command_is_err() {
+        let actual = ProcessRunner::new(OutputMode::Live).spawn(&ResolvedCommand::new(
+            "spinup-command-that-does-not-exist",
+            &[],
+        ));
+        assert!(actual.is_err());
+    }
+
+    fn with_timeout(args: &[&str], timeout: Duration) -> ResolvedCommand {
+        let args: Vec<String> = args.iter().map(|arg| String::from(*arg)).collect();
+        ResolvedCommand {
+            timeout: Some(timeout),
+            ..ResolvedCommand::new("sh", &args)
+        }
+    }
+
+    #[test]
+    fn test_process_runner_stops_commands_that_time_out() {
+        let started = Instant::now();
+        let actual = ProcessRunner::default().spawn(&with_timeout(
+            &["-c", "sleep 30 & sleep 30; wait"],
+            Duration::from_millis(200),
+        ));
+        match actual {
+            Err(Error::Timeout { command, timeout }) => {
+                assert_eq!(command, "sh -c \"sleep 30 & sleep 30; wait\"");
+                assert_eq!(timeout, Duration::from_millis(200));
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_process_runner_does_not_wait_on_escaped_processes() {
+        let started = Instant::now();
+        let actual = ProcessRunner::new(OutputMode::OnFailure).spawn(&with_timeout(
+            &["-c", "setsid sleep 5 & sleep 30"],
+            Duration::from_millis(200),
+        ));
+        assert!(matches!(actual, Err(Error::Timeout { .. })));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_process_runner_output_within_timeout() {
+        let actual = ProcessRunner::default()
+            .spawn(&with_timeout(
+                &["-c", "echo hello; exit 3"],
+                Duration::from_secs(10),
+            ))
+            .unwrap();
+        assert_eq!(actual.code, Some(3));
+        assert_eq!(actual.stdout, b"hello\n".to_vec());
     }
 }
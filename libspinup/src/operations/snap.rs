@@ -1,16 +1,38 @@
 use crate::configuration::Configuration;
 use crate::error::Result;
 
-use super::run_command;
+use super::{
+    run_command, CommandRunner, Conditional, Failures, Phase, RunReport, RunnableOperation,
+};
 
-pub fn install_snap_packages(config: &Configuration) -> Result<()> {
+pub fn install_snap_packages(
+    config: &Configuration,
+    runner: &dyn CommandRunner,
+    report: &RunReport,
+) -> Result<()> {
+    let mut failures = Failures::default();
+    let result = visit_snap_operations(config, &mut |op| {
+        let result = run_command(Phase::Snaps, op, &config.system_details, runner, report);
+        failures.allow(result, config.continue_on_error || op.continue_on_error())
+    });
+    failures.gather(result)?;
+    failures.into_result()
+}
+
+/// Walk the snap install operations in the order they should be run,
+/// passing each one to `visit`.
+pub(crate) fn visit_snap_operations(
+    config: &Configuration,
+    visit: &mut dyn FnMut(&dyn RunnableOperation) -> Result<()>,
+) -> Result<()> {
     match &config.snaps {
         Some(snaps) => {
-            run_command(&snaps.standard_snaps, config.system_details)?;
+            let when = snaps.when.as_ref();
+            visit(&Conditional::new(&snaps.standard_snaps, when))?;
 
             if let Some(additional_snaps) = &snaps.alternate_snaps {
                 for snap in additional_snaps {
-                    run_command(snap, config.system_details)?;
+                    visit(&Conditional::new(snap, when))?;
                 }
             }
             Ok(())
@@ -18,3 +40,46 @@ pub fn install_snap_packages(config: &Configuration) -> Result<()> {
         None => Ok(()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::{
+        SnapChannel, SnapPackage, Snaps, StandardSnaps, SystemDetails, TargetOperatingSystem,
+    };
+    use crate::operations::RecordingRunner;
+
+    #[test]
+    fn test_install_snap_packages_records_all_snaps() {
+        let mut config: Configuration = serde_json::from_str("{}").unwrap();
+        config.system_details = SystemDetails::new(TargetOperatingSystem::Arch);
+        config.snaps = Some(Snaps {
+            standard_snaps: StandardSnaps {
+                names: vec![String::from("spotify")],
+                continue_on_error: false,
+            },
+            alternate_snaps: Some(vec![SnapPackage {
+                name: String::from("code"),
+                classic: true,
+                channel: SnapChannel::Edge,
+                continue_on_error: false,
+                when: None,
+            }]),
+            when: None,
+        });
+
+        let runner = RecordingRunner::new();
+        let report = RunReport::new();
+        assert!(install_snap_packages(&config, &runner, &report).is_ok());
+        let commands: Vec<String> = runner.commands().iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            commands,
+            vec![
+                "sudo snap install spotify",
+                "sudo snap install code --edge --classic",
+            ]
+        );
+        assert_eq!(runner.root_requests(), 2);
+        assert_eq!(report.entries().len(), 2);
+    }
+}
@@ -1,6 +1,32 @@
 //! The run config is a struct used to define the settings used to
 //! invoke [`run_app`](fn.run_app.html)
 
+use std::collections::BTreeMap;
+
+/// The output format used when printing a plan instead of running
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PlanFormat {
+    /// One human readable line per step
+    Text,
+
+    /// A json document containing every step
+    Json,
+}
+
+/// When the output of the commands that are run is shown
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub enum OutputMode {
+    /// Each line is shown as soon as it is written
+    #[default]
+    Live,
+
+    /// The output of a command is only shown once it has failed
+    OnFailure,
+
+    /// The output is never shown, it is still kept for the run report
+    Hidden,
+}
+
 #[derive(Debug, Clone)]
 pub struct RunConfig {
     pub(crate) log_level: &'static str,
@@ -10,6 +36,12 @@ pub struct RunConfig {
     pub(crate) run_custom_commands: bool,
     pub(crate) config_file_path: String,
     pub(crate) print_parsed: bool,
+    pub(crate) plan: Option<PlanFormat>,
+    pub(crate) report_path: Option<String>,
+    pub(crate) keep_going: bool,
+    pub(crate) force_downloads: bool,
+    pub(crate) vars: BTreeMap<String, String>,
+    pub(crate) output: OutputMode,
 }
 
 impl RunConfig {
@@ -30,6 +62,54 @@ impl RunConfig {
             run_custom_commands,
             config_file_path,
             print_parsed,
+            plan: None,
+            report_path: None,
+            keep_going: false,
+            force_downloads: false,
+            vars: BTreeMap::new(),
+            output: OutputMode::default(),
         }
     }
+
+    /// Print the commands that would be run in the given `format`,
+    /// instead of running them
+    pub fn with_plan(mut self, format: PlanFormat) -> Self {
+        self.plan = Some(format);
+        self
+    }
+
+    /// Write a json report of every operation that was run to `report_path`
+    /// once the run finishes
+    pub fn with_report(mut self, report_path: String) -> Self {
+        self.report_path = Some(report_path);
+        self
+    }
+
+    /// Carry on when any operation fails, reporting all failures at the end
+    /// of the run instead of stopping at the first one
+    pub fn with_keep_going(mut self) -> Self {
+        self.keep_going = true;
+        self
+    }
+
+    /// Download every file again, instead of skipping files that have not
+    /// changed since they were last downloaded
+    pub fn with_force_downloads(mut self) -> Self {
+        self.force_downloads = true;
+        self
+    }
+
+    /// Set the variable `name` to `value`, taking precedence over the
+    /// `vars` defined in the configuration file
+    pub fn with_var(mut self, name: String, value: String) -> Self {
+        self.vars.insert(name, value);
+        self
+    }
+
+    /// Choose when the output of the commands that are run is shown,
+    /// by default each line is shown as soon as it is written
+    pub fn with_output(mut self, output: OutputMode) -> Self {
+        self.output = output;
+        self
+    }
 }
@@ -4,7 +4,7 @@ extern crate clap;
 extern crate log;
 
 use clap::{App, Arg};
-use libspinup::{run_app, RunConfig};
+use libspinup::{run_app, OutputMode, PlanFormat, RunConfig};
 
 const DEFAULT_LOG_LEVEL: &str = "warn";
 
@@ -31,6 +31,15 @@ async fn main() {
                 .takes_value(false)
                 .conflicts_with("verbose"),
         )
+        .arg(
+            Arg::with_name("command-output")
+                .long("command-output")
+                .value_name("WHEN")
+                .help("When to show the output of the commands that are run, as they run or only when they fail")
+                .takes_value(true)
+                .possible_values(&["live", "on-failure"])
+                .conflicts_with("quiet"),
+        )
         .arg(
             Arg::with_name("no-packages")
                 .short("P")
@@ -71,6 +80,58 @@ async fn main() {
                 .multiple(false)
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("plan")
+                .long("plan")
+                .help("Print the commands that would be run, without running anything")
+                .multiple(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("plan-format")
+                .long("plan-format")
+                .help("The output format of the plan")
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .requires("plan"),
+        )
+        .arg(
+            Arg::with_name("keep-going")
+                .short("k")
+                .long("keep-going")
+                .help("Carry on when an operation fails, and list every failure at the end")
+                .multiple(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("force-downloads")
+                .long("force-downloads")
+                .help("Download every file again, even when it has not changed since the last run")
+                .multiple(false)
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("report")
+                .long("report")
+                .value_name("FILE")
+                .help("Write a json report of every operation that was run to FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("var")
+                .long("var")
+                .value_name("NAME=VALUE")
+                .help("Set a config variable, overriding the value in the config file")
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|value| {
+                    if value.contains('=') {
+                        Ok(())
+                    } else {
+                        Err(String::from("variables must be given as NAME=VALUE"))
+                    }
+                }),
+        )
         .arg(
             Arg::with_name("CONFIG")
                 .help("The input configuration file")
@@ -108,7 +169,7 @@ fn build_run_config(matches: clap::ArgMatches) -> RunConfig {
         matches.occurrences_of("verbose"),
         matches.is_present("quiet"),
     );
-    RunConfig::new(
+    let mut run_config = RunConfig::new(
         matches.value_of("CONFIG").unwrap().to_owned(),
         log_level,
         !matches.is_present("no-packages"),
@@ -116,7 +177,43 @@ fn build_run_config(matches: clap::ArgMatches) -> RunConfig {
         !matches.is_present("no-snaps"),
         !matches.is_present("no-commands"),
         matches.is_present("print-parsed"),
-    )
+    );
+
+    if matches.is_present("keep-going") {
+        run_config = run_config.with_keep_going();
+    }
+
+    if matches.is_present("force-downloads") {
+        run_config = run_config.with_force_downloads();
+    }
+
+    if matches.is_present("quiet") {
+        run_config = run_config.with_output(OutputMode::Hidden);
+    } else if matches.value_of("command-output") == Some("on-failure") {
+        run_config = run_config.with_output(OutputMode::OnFailure);
+    }
+
+    if let Some(vars) = matches.values_of("var") {
+        for var in vars {
+            let mut parts = var.splitn(2, '=');
+            let name = parts.next().unwrap_or_default();
+            let value = parts.next().unwrap_or_default();
+            run_config = run_config.with_var(name.to_owned(), value.to_owned());
+        }
+    }
+
+    if let Some(report_path) = matches.value_of("report") {
+        run_config = run_config.with_report(report_path.to_owned());
+    }
+
+    if !matches.is_present("plan") {
+        return run_config;
+    }
+
+    match matches.value_of("plan-format") {
+        Some("json") => run_config.with_plan(PlanFormat::Json),
+        _ => run_config.with_plan(PlanFormat::Text),
+    }
 }
 
 fn get_log_level(verbosity: u64, is_quiet: bool) -> &'static str {